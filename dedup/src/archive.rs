@@ -0,0 +1,192 @@
+use crate::find;
+use crypto_hash::Hasher;
+use flate2::read::GzDecoder;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use xz2::read::XzDecoder;
+
+/// Separates the archive path from the member path in a virtual path, as in
+/// `backup.zip!/docs/a.pdf`.
+const MEMBER_SEPARATOR: &str = "!/";
+
+#[derive(Debug, PartialEq)]
+enum Format {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+    TarXz,
+}
+
+/// A regular file inside an archive, hashed like files on disk.
+#[derive(Debug)]
+pub struct Member {
+    pub path: PathBuf,
+    pub size: u64,
+    pub hash: String,
+}
+
+pub fn is_archive<P>(path: P) -> bool
+where
+    P: AsRef<Path>,
+{
+    get_format(path.as_ref()).is_some()
+}
+
+/// Stream and hash all non-empty regular file members of a zip, tar, tar.gz,
+/// tar.zst or tar.xz archive.
+pub fn members<P>(archive: P) -> io::Result<Vec<Member>>
+where
+    P: AsRef<Path>,
+{
+    let archive = archive.as_ref();
+    let file = BufReader::new(File::open(archive)?);
+
+    match get_format(archive) {
+        Some(Format::Zip) => zip_members(archive, file),
+        Some(Format::Tar) => tar_members(archive, file),
+        Some(Format::TarGz) => tar_members(archive, GzDecoder::new(file)),
+        Some(Format::TarZst) => tar_members(archive, zstd::Decoder::new(file)?),
+        Some(Format::TarXz) => tar_members(archive, XzDecoder::new(file)),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a supported archive", archive.display()),
+        )),
+    }
+}
+
+fn zip_members<R>(archive: &Path, reader: R) -> io::Result<Vec<Member>>
+where
+    R: Read + io::Seek,
+{
+    let mut zip = zip::ZipArchive::new(reader)?;
+
+    let mut members = vec![];
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i)?;
+        if !entry.is_file() || entry.size() == 0 {
+            continue;
+        }
+
+        let path = virtual_path(archive, Path::new(entry.name()));
+        let size = entry.size();
+        members.push(Member {
+            path,
+            size,
+            hash: hash_reader(&mut entry)?,
+        });
+    }
+
+    Ok(members)
+}
+
+fn tar_members<R>(archive: &Path, reader: R) -> io::Result<Vec<Member>>
+where
+    R: Read,
+{
+    let mut tar = tar::Archive::new(reader);
+
+    let mut members = vec![];
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() || entry.size() == 0 {
+            continue;
+        }
+
+        let path = virtual_path(archive, &entry.path()?);
+        let size = entry.size();
+        members.push(Member {
+            path,
+            size,
+            hash: hash_reader(&mut entry)?,
+        });
+    }
+
+    Ok(members)
+}
+
+fn hash_reader<R>(reader: &mut R) -> io::Result<String>
+where
+    R: Read,
+{
+    let mut hasher = Hasher::new(find::HASH_ALGORITHM);
+    io::copy(reader, &mut hasher)?;
+
+    Ok(hex::encode(hasher.finish()))
+}
+
+fn virtual_path(archive: &Path, member: &Path) -> PathBuf {
+    let mut path = OsString::from(archive.as_os_str());
+    path.push(MEMBER_SEPARATOR);
+    path.push(member.as_os_str());
+
+    PathBuf::from(path)
+}
+
+fn get_format(path: &Path) -> Option<Format> {
+    let name = path.file_name()?.to_str()?.to_lowercase();
+
+    if name.ends_with(".zip") {
+        Some(Format::Zip)
+    } else if name.ends_with(".tar") {
+        Some(Format::Tar)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(Format::TarGz)
+    } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+        Some(Format::TarZst)
+    } else if name.ends_with(".tar.xz") || name.ends_with(".txz") {
+        Some(Format::TarXz)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::archive;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::Path;
+
+    #[test]
+    fn read_members() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let zip_path = dir.path().join("backup.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        zip.start_file("docs/a.txt", Default::default()).unwrap();
+        zip.write_all(b"Hello").unwrap();
+        zip.start_file("empty.txt", Default::default()).unwrap();
+        zip.finish().unwrap();
+
+        let tar_path = dir.path().join("backup.tar.gz");
+        let gz = GzEncoder::new(File::create(&tar_path).unwrap(), Compression::default());
+        let mut tar = tar::Builder::new(gz);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(5);
+        header.set_cksum();
+        tar.append_data(&mut header, "docs/a.txt", &b"Hello"[..])
+            .unwrap();
+        tar.into_inner().unwrap().finish().unwrap();
+
+        let hash = crypto_hash::hex_digest(crypto_hash::Algorithm::SHA256, b"Hello");
+        for (archive, member) in &[
+            (zip_path, "backup.zip!/docs/a.txt"),
+            (tar_path, "backup.tar.gz!/docs/a.txt"),
+        ] {
+            assert!(archive::is_archive(archive));
+
+            let members = archive::members(archive).unwrap();
+            assert_eq!(members.len(), 1);
+            assert_eq!(members[0].path, dir.path().join(member));
+            assert_eq!(members[0].size, 5);
+            assert_eq!(members[0].hash, hash);
+        }
+
+        assert!(!archive::is_archive(Path::new("backup.gz")));
+    }
+}
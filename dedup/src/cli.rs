@@ -1,5 +1,13 @@
+use crate::find::{self, Identity};
 use crate::keep::KeepCriteria;
+use crate::metadata::{MergeOptions, ModeMerge};
+use crate::progress;
+use crate::report::SortKey;
+use crate::script::Shell;
+use crate::similar::ImageHash;
 use pico_args as pico;
+use std::env;
+use std::ffi::OsString;
 use std::path::{Path, PathBuf};
 use std::process;
 use std::str::FromStr;
@@ -11,25 +19,137 @@ Find and delete duplicate files.
 
 USAGE:
     dedup [OPTIONS] PATH...
+    dedup [OPTIONS] --files-from FILE
+    dedup find [OPTIONS] [FIND OPTIONS] PATH...
+    dedup watch [OPTIONS] DIR...
+
+SUBCOMMANDS:
+    find                List duplicates without deleting anything.
+    watch               Index DIRs, then keep watching them and delete
+                        duplicates as soon as a file written or moved into
+                        them duplicates an indexed file. Only deletes, and
+                        ignores options for finding other kinds of duplicates.
 
 OPTIONS:
     -h, --help          Displays help information.
     -v, --version       Displays version information.
     -d, --dry-run       Output only and don't perform any deletion.
-    -k, --keep-criteria [oldest, newest, shallowest, deepest]
+    -k, --keep-criteria [oldest, newest, shallowest, deepest, highest-resolution]
                         Criteria of which file to keep from duplicates. Defaults
-                        to newest.";
+                        to newest. highest-resolution is only available, and
+                        the default, with --similar-images.
+    -M, --match [content, content+mode, content+owner, content+xattrs]
+                        What must be identical for files to be duplicates.
+                        Defaults to content.
+        --samples COUNT Number of blocks, up to 64, spaced evenly from start to
+                        end of equally sized files to hash before hashing them
+                        whole. Defaults to 3, for start, middle and end.
+        --sample-size BYTES
+                        Size of each sampled block, up to 1M. Defaults to the
+                        block size of the filesystem of the first PATH.
+        --memory-limit SIZE
+                        Spill candidates to temporary files once they take
+                        about SIZE bytes of memory, with an optional K, M or G
+                        suffix. Duplicates found are unchanged. Defaults to no
+                        limit.
+        --progress [auto, always, never, json]
+                        Report progress to stderr as a status line, or as a
+                        JSON record per line. Defaults to auto, a status line
+                        only if stderr is a terminal.
+        --stats         Report how many candidates each stage of finding
+                        duplicates eliminated, to stderr.
+        --files-from FILE
+                        Consider exactly the files listed in FILE, or stdin if
+                        FILE is -, instead of walking PATHs, which must then
+                        be left out. Not available with --dirs or watch.
+    -0, --null          Separate --files-from entries by NUL instead of
+                        newline.
+    -a, --scan-archives Also find duplicates inside zip, tar, tar.gz, tar.zst and
+                        tar.xz archives. Archive members are never deleted, but
+                        files duplicated by one are. Not available with --dirs
+                        or --similar-images.
+    -D, --dirs          Find and delete duplicate directory trees instead of
+                        files.
+    -s, --similar-images
+                        Find and delete visually similar JPEG, PNG, WebP and
+                        GIF images instead of identical files.
+        --image-hash [ahash, dhash, phash]
+                        Perceptual hash used by --similar-images. Defaults to
+                        phash.
+        --max-distance DISTANCE
+                        Maximum Hamming distance between the 64 bit hashes of
+                        similar images. Defaults to 8.
+    -o, --overlap THRESHOLD
+                        Only report pairs of files whose content defined chunks
+                        have a Jaccard similarity of at least THRESHOLD, from 0
+                        to 1, without deleting anything. Not available with
+                        --dirs or --similar-images.
+    -p, --prune-empty-dirs
+                        Delete directories left empty after deleting
+                        duplicates, except the given PATHs. Not available
+                        with --files-from.
+    -m, --merge-metadata [mtime, atime, xattrs, permissive-mode, restrictive-mode]
+                        Comma separated metadata of deleted duplicates to merge
+                        onto the kept file. Times are set to the earliest,
+                        missing extended attributes are copied and permissions
+                        are unioned or intersected. Not applied to scripts.
+    -t, --top COUNT     Number of directories holding the most redundant bytes
+                        to list in the summary. Defaults to 10.
+    -e, --emit-script [sh, fish]
+                        Write a script that deletes duplicates to stdout for
+                        review instead of deleting them.
+        --print0        Write paths of duplicates that would be deleted to
+                        stdout, each terminated by NUL, instead of deleting
+                        them. Not applied to empty directories. Not available
+                        with --emit-script.
+
+FIND OPTIONS:
+        --report-only   Same as the find subcommand, only listing duplicates
+                        without choosing which to keep or deleting anything.
+        --sort [wasted, copies, path]
+                        Order of listed duplicates. Defaults to wasted.
+        --min-copies N  Only list duplicates with at least N copies. Defaults
+                        to 2.
+        --touching DIR  Only list duplicates with a copy inside DIR, compared
+                        as listed.";
 const VERSION: &str = "dedup 0.8";
 
 #[derive(Debug)]
 pub struct Args {
     pub paths: Vec<PathBuf>,
+    pub files_from: Option<PathBuf>,
+    pub null: bool,
     pub dry_run: bool,
     pub keep_criteria: KeepCriteria,
+    pub identity: Identity,
+    pub samples: u64,
+    pub sample_size: Option<u64>,
+    pub memory_limit: Option<u64>,
+    pub progress: progress::Mode,
+    pub stats: bool,
+    pub scan_archives: bool,
+    pub dirs: bool,
+    pub similar_images: bool,
+    pub image_hash: ImageHash,
+    pub max_distance: u32,
+    pub overlap: Option<f64>,
+    pub prune_empty_dirs: bool,
+    pub merge_metadata: MergeOptions,
+    pub top: usize,
+    pub emit_script: Option<Shell>,
+    pub print0: bool,
+    pub report_only: bool,
+    pub watch: bool,
+    pub sort: SortKey,
+    pub min_copies: usize,
+    pub touching: Option<PathBuf>,
 }
 
 pub fn get_args() -> Args {
-    match parse_args() {
+    let args = parse_args()
+        .map_err(|err| err.to_string())
+        .and_then(|args| check(&args).map(|()| args));
+    match args {
         Ok(args) => args,
         Err(err) => {
             eprintln!("{}", err);
@@ -40,7 +160,15 @@ pub fn get_args() -> Args {
 }
 
 fn parse_args() -> Result<Args, pico::Error> {
-    let mut pargs = pico::Arguments::from_env();
+    let mut args = env::args_os().skip(1).collect::<Vec<OsString>>();
+    let subcommand = match args.first().and_then(|a| a.to_str()) {
+        Some(s @ "find") | Some(s @ "watch") => Some(s.to_string()),
+        _ => None,
+    };
+    if subcommand.is_some() {
+        args.remove(0);
+    }
+    let mut pargs = pico::Arguments::from_vec(args);
 
     if pargs.contains(["-h", "--help"]) {
         print!("{}", HELP);
@@ -51,9 +179,48 @@ fn parse_args() -> Result<Args, pico::Error> {
     }
 
     let dry_run = pargs.contains(["-d", "--dry-run"]);
-    let keep_criteria = pargs
-        .opt_value_from_str(["-k", "--keep-criteria"])?
-        .unwrap_or(KeepCriteria::Newest);
+    let keep_criteria = pargs.opt_value_from_str(["-k", "--keep-criteria"])?;
+    let identity = pargs
+        .opt_value_from_str(["-M", "--match"])?
+        .unwrap_or(Identity::Content);
+    let samples = pargs.opt_value_from_str("--samples")?.unwrap_or(3);
+    let sample_size = pargs.opt_value_from_str("--sample-size")?;
+    let memory_limit = pargs.opt_value_from_fn("--memory-limit", parse_size)?;
+    let progress = pargs
+        .opt_value_from_str("--progress")?
+        .unwrap_or(progress::Mode::Auto);
+    let stats = pargs.contains("--stats");
+    let files_from =
+        pargs.opt_value_from_os_str("--files-from", |s| Ok::<PathBuf, String>(PathBuf::from(s)))?;
+    let null = pargs.contains(["-0", "--null"]);
+    let scan_archives = pargs.contains(["-a", "--scan-archives"]);
+    let dirs = pargs.contains(["-D", "--dirs"]);
+    let similar_images = pargs.contains(["-s", "--similar-images"]);
+    let keep_criteria = keep_criteria.unwrap_or(if similar_images {
+        KeepCriteria::HighestResolution
+    } else {
+        KeepCriteria::Newest
+    });
+    let image_hash = pargs
+        .opt_value_from_str("--image-hash")?
+        .unwrap_or(ImageHash::Perceptual);
+    let max_distance = pargs.opt_value_from_str("--max-distance")?.unwrap_or(8);
+    let overlap = pargs.opt_value_from_str(["-o", "--overlap"])?;
+    let prune_empty_dirs = pargs.contains(["-p", "--prune-empty-dirs"]);
+    let merge_metadata = pargs
+        .opt_value_from_str(["-m", "--merge-metadata"])?
+        .unwrap_or_default();
+    let top = pargs.opt_value_from_str(["-t", "--top"])?.unwrap_or(10);
+    let emit_script = pargs.opt_value_from_str(["-e", "--emit-script"])?;
+    let print0 = pargs.contains("--print0");
+    let report_only = subcommand.as_deref() == Some("find") || pargs.contains("--report-only");
+    let watch = subcommand.as_deref() == Some("watch");
+    let sort = pargs
+        .opt_value_from_str("--sort")?
+        .unwrap_or(SortKey::Wasted);
+    let min_copies = pargs.opt_value_from_str("--min-copies")?.unwrap_or(2);
+    let touching =
+        pargs.opt_value_from_os_str("--touching", |s| Ok::<PathBuf, String>(PathBuf::from(s)))?;
     let paths = pargs
         .finish()
         .into_iter()
@@ -62,11 +229,100 @@ fn parse_args() -> Result<Args, pico::Error> {
 
     Ok(Args {
         paths,
+        files_from,
+        null,
         dry_run,
         keep_criteria,
+        identity,
+        samples,
+        sample_size,
+        memory_limit,
+        progress,
+        stats,
+        scan_archives,
+        dirs,
+        similar_images,
+        image_hash,
+        max_distance,
+        overlap,
+        prune_empty_dirs,
+        merge_metadata,
+        top,
+        emit_script,
+        print0,
+        report_only,
+        watch,
+        sort,
+        min_copies,
+        touching,
     })
 }
 
+/// Reject combinations of options that can't work together.
+fn check(args: &Args) -> Result<(), String> {
+    if args.files_from.is_some() && !args.paths.is_empty() {
+        return Err("--files-from lists the files to consider, so takes no PATHs.".to_string());
+    }
+    if args.watch && args.paths.is_empty() {
+        return Err("watch needs at least one DIR to watch.".to_string());
+    }
+    if args.files_from.is_some() && (args.dirs || args.watch) {
+        return Err("--dirs and watch walk PATHs, so can't take --files-from.".to_string());
+    }
+    if args.prune_empty_dirs && args.files_from.is_some() {
+        return Err("--prune-empty-dirs needs PATHs to prune up to, not --files-from.".to_string());
+    }
+    if args.samples == 0 || args.samples > find::MAX_SAMPLE_COUNT {
+        return Err(format!(
+            "--samples must be between 1 and {}.",
+            find::MAX_SAMPLE_COUNT
+        ));
+    }
+    if let Some(size) = args.sample_size {
+        if size == 0 || size > find::MAX_SAMPLE_SIZE {
+            return Err(format!(
+                "--sample-size must be between 1 and {} bytes.",
+                find::MAX_SAMPLE_SIZE
+            ));
+        }
+    }
+    if matches!(args.keep_criteria, KeepCriteria::HighestResolution) && !args.similar_images {
+        return Err("--keep-criteria highest-resolution needs --similar-images.".to_string());
+    }
+    if args.emit_script.is_some() && args.print0 {
+        return Err("--emit-script and --print0 both write to stdout, so pick one.".to_string());
+    }
+    if args.overlap.is_some() && (args.dirs || args.similar_images) {
+        return Err(
+            "--overlap compares files by content, not --dirs or --similar-images.".to_string(),
+        );
+    }
+    if args.scan_archives && (args.dirs || args.similar_images) {
+        return Err(
+            "--scan-archives only applies to identical files, not --dirs or --similar-images."
+                .to_string(),
+        );
+    }
+
+    Ok(())
+}
+
+/// Parse bytes with an optional binary K, M or G suffix.
+fn parse_size(s: &str) -> Result<u64, String> {
+    let (digits, multiplier) = match s.chars().last() {
+        Some('K') | Some('k') => (&s[..s.len() - 1], 1 << 10),
+        Some('M') | Some('m') => (&s[..s.len() - 1], 1 << 20),
+        Some('G') | Some('g') => (&s[..s.len() - 1], 1 << 30),
+        _ => (s, 1),
+    };
+
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid size '{}'.", s))
+}
+
 impl FromStr for KeepCriteria {
     type Err = String;
 
@@ -76,7 +332,97 @@ impl FromStr for KeepCriteria {
             "newest" => Ok(KeepCriteria::Newest),
             "shallowest" => Ok(KeepCriteria::Shallowest),
             "deepest" => Ok(KeepCriteria::Deepest),
+            "highest-resolution" => Ok(KeepCriteria::HighestResolution),
             _ => Err(format!("invalid keep criteria '{}'.", s)),
         }
     }
 }
+
+impl FromStr for Identity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "content" => Ok(Identity::Content),
+            "content+mode" => Ok(Identity::ContentMode),
+            "content+owner" => Ok(Identity::ContentOwner),
+            "content+xattrs" => Ok(Identity::ContentXattrs),
+            _ => Err(format!("invalid match '{}'.", s)),
+        }
+    }
+}
+
+impl FromStr for ImageHash {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ahash" => Ok(ImageHash::Average),
+            "dhash" => Ok(ImageHash::Difference),
+            "phash" => Ok(ImageHash::Perceptual),
+            _ => Err(format!("invalid image hash '{}'.", s)),
+        }
+    }
+}
+
+impl FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wasted" => Ok(SortKey::Wasted),
+            "copies" => Ok(SortKey::Copies),
+            "path" => Ok(SortKey::Path),
+            _ => Err(format!("invalid sort '{}'.", s)),
+        }
+    }
+}
+
+impl FromStr for Shell {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sh" => Ok(Shell::Sh),
+            "fish" => Ok(Shell::Fish),
+            _ => Err(format!("invalid shell '{}'.", s)),
+        }
+    }
+}
+
+impl FromStr for progress::Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(progress::Mode::Auto),
+            "always" => Ok(progress::Mode::Always),
+            "never" => Ok(progress::Mode::Never),
+            "json" => Ok(progress::Mode::Json),
+            _ => Err(format!("invalid progress '{}'.", s)),
+        }
+    }
+}
+
+impl FromStr for MergeOptions {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut options = MergeOptions::default();
+        for item in s.split(',') {
+            match item {
+                "mtime" => options.mtime = true,
+                "atime" => options.atime = true,
+                "xattrs" => options.xattrs = true,
+                "permissive-mode" | "restrictive-mode" if options.mode.is_some() => {
+                    return Err("only one of permissive-mode and restrictive-mode.".to_string())
+                }
+                "permissive-mode" => options.mode = Some(ModeMerge::Permissive),
+                "restrictive-mode" => options.mode = Some(ModeMerge::Restrictive),
+                _ => return Err(format!("invalid metadata '{}'.", item)),
+            }
+        }
+
+        Ok(options)
+    }
+}
@@ -1,28 +1,119 @@
+use std::collections::VecDeque;
+use std::ffi::OsString;
 use std::fs;
-use std::io;
+use std::io::{self, BufRead};
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
+use std::vec;
 
 /// Walk descendant files of directory.
 pub fn walk_files<P>(directory: P) -> io::Result<Vec<PathBuf>>
 where
     P: AsRef<Path>,
 {
-    let mut files = vec![];
+    walk(directory).collect()
+}
+
+/// Lazily walk descendant files of directory, breadth first, holding only
+/// the files of one directory and the directories still to walk at a time.
+pub fn walk<P>(directory: P) -> Walk
+where
+    P: AsRef<Path>,
+{
+    Walk {
+        files: vec![].into_iter(),
+        dirs: VecDeque::from(vec![directory.as_ref().to_path_buf()]),
+    }
+}
+
+pub struct Walk {
+    files: vec::IntoIter<PathBuf>,
+    dirs: VecDeque<PathBuf>,
+}
+
+impl Iterator for Walk {
+    type Item = io::Result<PathBuf>;
 
-    let mut parents = vec![directory.as_ref().to_path_buf()];
-    while !parents.is_empty() {
-        let mut new_parents = vec![];
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(f) = self.files.next() {
+                return Some(Ok(f));
+            }
 
-        for dir in parents {
-            let (mut child_files, mut child_dirs) = partition_directory_children(dir)?;
-            files.append(&mut child_files);
-            new_parents.append(&mut child_dirs);
+            let dir = self.dirs.pop_front()?;
+            match partition_directory_children(dir) {
+                Ok((files, dirs)) => {
+                    self.files = files.into_iter();
+                    self.dirs.extend(dirs);
+                }
+                Err(err) => return Some(Err(err)),
+            }
         }
+    }
+}
 
-        parents = new_parents;
+/// Walk descendant directories of directory, breadth first.
+pub fn walk_dirs<P>(directory: P) -> io::Result<Vec<PathBuf>>
+where
+    P: AsRef<Path>,
+{
+    let mut dirs = vec![];
+    let mut pending = VecDeque::from(vec![directory.as_ref().to_path_buf()]);
+    while let Some(dir) = pending.pop_front() {
+        let (_, children) = partition_directory_children(dir)?;
+        dirs.extend(children.iter().cloned());
+        pending.extend(children);
     }
 
-    Ok(files)
+    Ok(dirs)
+}
+
+/// Lazily read paths from a list separated by `separator`, such as newline
+/// or NUL, as raw bytes so any path can be listed. Empty entries are skipped.
+pub fn read_file_list<R>(reader: R, separator: u8) -> FileList<R>
+where
+    R: BufRead,
+{
+    FileList { reader, separator }
+}
+
+pub struct FileList<R> {
+    reader: R,
+    separator: u8,
+}
+
+impl<R> Iterator for FileList<R>
+where
+    R: BufRead,
+{
+    type Item = io::Result<PathBuf>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let mut entry = vec![];
+            match self.reader.read_until(self.separator, &mut entry) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(err) => return Some(Err(err)),
+            }
+
+            if entry.last() == Some(&self.separator) {
+                entry.pop();
+            }
+            if !entry.is_empty() {
+                return Some(Ok(PathBuf::from(OsString::from_vec(entry))));
+            }
+        }
+    }
+}
+
+/// Preferred IO block size of the filesystem holding `path`.
+pub fn block_size<P>(path: P) -> io::Result<u64>
+where
+    P: AsRef<Path>,
+{
+    Ok(fs::metadata(path)?.blksize())
 }
 
 /// Partition children of directory into a pair of files and directories.
@@ -36,3 +127,30 @@ where
 
     Ok(children.into_iter().partition(|c| c.is_file()))
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::filesystem;
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::PathBuf;
+
+    #[test]
+    fn read_file_lists() {
+        let list = filesystem::read_file_list(&b"a b\n\nc\xff\n"[..], b'\n')
+            .collect::<Result<Vec<PathBuf>, _>>()
+            .unwrap();
+        assert_eq!(
+            list,
+            vec![
+                PathBuf::from("a b"),
+                PathBuf::from(OsStr::from_bytes(b"c\xff"))
+            ]
+        );
+
+        let list = filesystem::read_file_list(&b"a\nb\0c"[..], 0)
+            .collect::<Result<Vec<PathBuf>, _>>()
+            .unwrap();
+        assert_eq!(list, vec![PathBuf::from("a\nb"), PathBuf::from("c")]);
+    }
+}
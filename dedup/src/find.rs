@@ -1,72 +1,350 @@
+use crate::archive::{self, Member};
+use crate::index::SizeIndex;
+use crate::progress::Progress;
 use crypto_hash::{self, Algorithm, Hasher};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::{self, File};
 use std::hash::Hash;
-use std::io::{self, BufReader, Read, Write};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
 
-const HEAD_SIZE: usize = 4_000;
+const READ_SIZE: usize = 4_000;
+const DEFAULT_SAMPLE_COUNT: u64 = 3;
+const DEFAULT_SAMPLE_SIZE: u64 = 4_096;
+pub const MAX_SAMPLE_SIZE: u64 = 1 << 20;
+pub const MAX_SAMPLE_COUNT: u64 = 64;
 const FILE_BUFFER_SIZE: usize = 16_000;
-const HASH_ALGORITHM: Algorithm = Algorithm::SHA256;
+pub const HASH_ALGORITHM: Algorithm = Algorithm::SHA256;
 
 #[derive(Debug)]
 pub struct Duplicate {
     pub hash: String,
+    pub size: u64,
     pub files: Vec<PathBuf>,
+    /// Virtual paths of archive members with the same content, which are
+    /// never deleted.
+    pub archived: Vec<PathBuf>,
 }
 
 impl Duplicate {
-    pub fn new(hash: String, files: Vec<PathBuf>) -> Duplicate {
+    pub fn new(hash: String, size: u64, files: Vec<PathBuf>) -> Duplicate {
         if files.len() < 2 {
             panic!("files length must be greater than 2.");
         }
 
-        Duplicate { hash, files }
+        Duplicate {
+            hash,
+            size,
+            files,
+            archived: vec![],
+        }
+    }
+
+    /// Bytes taken by redundant copies, all files if an archive member is
+    /// kept and otherwise all but one.
+    pub fn wasted_bytes(&self) -> u64 {
+        let redundant = if self.archived.is_empty() {
+            self.files.len() - 1
+        } else {
+            self.files.len()
+        };
+
+        self.size * redundant as u64
+    }
+
+    pub fn with_archived(
+        hash: String,
+        size: u64,
+        files: Vec<PathBuf>,
+        archived: Vec<PathBuf>,
+    ) -> Duplicate {
+        if files.is_empty() || files.len() + archived.len() < 2 {
+            panic!("files and archived length must be greater than 2, with at least 1 file.");
+        }
+
+        Duplicate {
+            hash,
+            size,
+            files,
+            archived,
+        }
     }
 }
 
-/// Find all duplicate files under `directory`.
-///
-/// Zero byte files are ignored. An [`std::io::Error`] is returned immediately
-/// for any IO errors encountered.
-pub fn duplicate_files<P>(files: Vec<P>) -> io::Result<Vec<Duplicate>>
+/// Files with identical content that were split into `groups` by differing
+/// metadata.
+#[derive(Debug)]
+pub struct Split {
+    pub hash: String,
+    pub groups: Vec<Vec<PathBuf>>,
+}
+
+#[derive(Debug, Default)]
+pub struct Findings {
+    pub duplicates: Vec<Duplicate>,
+    pub splits: Vec<Split>,
+    pub stages: Vec<Stage>,
+}
+
+impl Findings {
+    /// Add to the counts of stage `name`, adding the stage if it's new.
+    fn count_stage(&mut self, name: &'static str, candidates: usize, remaining: usize) {
+        match self.stages.iter_mut().find(|s| s.name == name) {
+            Some(stage) => {
+                stage.candidates += candidates;
+                stage.remaining += remaining;
+            }
+            None => self.stages.push(Stage {
+                name,
+                candidates,
+                remaining,
+            }),
+        }
+    }
+}
+
+/// How many candidates a stage of [`duplicate_files`] received and how many
+/// it let through.
+#[derive(Debug)]
+pub struct Stage {
+    pub name: &'static str,
+    pub candidates: usize,
+    pub remaining: usize,
+}
+
+/// What besides content must be identical for files to be duplicates.
+#[derive(Debug, Clone)]
+pub enum Identity {
+    Content,
+    ContentMode,
+    ContentOwner,
+    ContentXattrs,
+}
+
+/// Blocks hashed to cheaply tell apart files of the same size before hashing
+/// them whole.
+#[derive(Debug)]
+pub struct Sampling {
+    /// Blocks spaced evenly from start to end, so 3 is start, middle and end.
+    pub count: u64,
+    pub size: u64,
+}
+
+#[derive(Debug)]
+pub struct Options {
+    pub identity: Identity,
+    pub sampling: Sampling,
+    /// Rough bytes of memory candidates may take before spilling to
+    /// temporary files, or no limit if `None`.
+    pub memory_limit: Option<u64>,
+    /// Read members of archives among the files as they're walked, as if
+    /// they were `archived` members.
+    pub scan_archives: bool,
+}
+
+impl Default for Sampling {
+    fn default() -> Sampling {
+        Sampling {
+            count: DEFAULT_SAMPLE_COUNT,
+            size: DEFAULT_SAMPLE_SIZE,
+        }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            identity: Identity::Content,
+            sampling: Sampling::default(),
+            memory_limit: None,
+            scan_archives: false,
+        }
+    }
+}
+
+/// Find all duplicates among `files`, see [`duplicates_in`].
+pub fn duplicate_files<P>(
+    files: Vec<P>,
+    options: &Options,
+    archived: &[Member],
+) -> io::Result<Findings>
 where
     P: AsRef<Path>,
 {
-    let files = files.into_iter().map(|p| p.as_ref().to_path_buf());
-
-    let files = omit_size_singletons(files)?;
-    let files = omit_head_hash_singletons(files)?;
-    let hash_groups = group_by_hash(files)?;
-
-    Ok(hash_groups
-        .into_iter()
-        .map(|(h, f)| Duplicate::new(h, f))
-        .collect())
+    let files = files.into_iter().map(|p| Ok(p.as_ref().to_path_buf()));
+    duplicates_in(files, options, archived, &Progress::hidden())
 }
 
-fn omit_size_singletons<I>(files: I) -> io::Result<Vec<PathBuf>>
+/// Find all duplicates among a stream of `files`.
+///
+/// Zero byte files are ignored. Candidates are narrowed down by size, then by
+/// hashes of sampled blocks and finally by whole content hash, with how
+/// effective each stage was reported in [`Findings::stages`]. Files with
+/// identical content are further split by the metadata `options.identity`
+/// requires, and such splits are reported in [`Findings::splits`]. Files are
+/// also duplicates of `archived` members with the same content, which only
+/// ever match by content, and of members of archives among `files` if
+/// `options.scan_archives`, skipping archives that can't be read.
+///
+/// Files are indexed by size, spilling to temporary files beyond
+/// `options.memory_limit`, and each spilled partition of sizes is then
+/// narrowed down on its own, with each stage reported to `progress`. An
+/// [`std::io::Error`] is returned immediately for any IO errors encountered.
+pub fn duplicates_in<I>(
+    files: I,
+    options: &Options,
+    archived: &[Member],
+    progress: &Progress,
+) -> io::Result<Findings>
 where
-    I: IntoIterator<Item = PathBuf>,
+    I: IntoIterator<Item = io::Result<PathBuf>>,
 {
-    let mut size_groups = group_by(files, |f| get_file_size(f))?;
-    size_groups.remove(&0);
-    Ok(ungroup(omit_singletons(size_groups)))
+    let mut index = SizeIndex::new(options.memory_limit);
+    let mut candidates = 0;
+    let mut scanned = vec![];
+    for f in files {
+        let f = f?;
+        let size = get_file_size(&f)?;
+        candidates += 1;
+        progress.walked();
+        if size != 0 {
+            index.insert(&f, size)?;
+        }
+        if options.scan_archives && archive::is_archive(&f) {
+            match archive::members(&f) {
+                Ok(m) => scanned.extend(m),
+                Err(err) => eprintln!("Skipping archive {}: {}", f.display(), err),
+            }
+        }
+    }
+
+    let archived = archived.iter().chain(&scanned);
+    let member_sizes = archived.clone().map(|m| m.size).collect::<HashSet<u64>>();
+    let mut member_groups = HashMap::<&str, Vec<PathBuf>>::new();
+    for m in archived {
+        member_groups
+            .entry(&m.hash)
+            .or_default()
+            .push(m.path.clone());
+    }
+
+    let mut findings = Findings::default();
+    findings.count_stage("size", candidates, 0);
+    index.for_each_partition(|size_groups| {
+        find_in_partition(
+            size_groups,
+            options,
+            &member_sizes,
+            &mut member_groups,
+            &mut findings,
+            progress,
+        )
+    })?;
+    progress.finish();
+
+    Ok(findings)
+}
+
+/// Narrow down one partition of size groups to duplicates.
+fn find_in_partition(
+    size_groups: HashMap<u64, Vec<PathBuf>>,
+    options: &Options,
+    member_sizes: &HashSet<u64>,
+    member_groups: &mut HashMap<&str, Vec<PathBuf>>,
+    findings: &mut Findings,
+    progress: &Progress,
+) -> io::Result<()> {
+    let (files, member_sized) = omit_size_singletons(size_groups, member_sizes);
+    findings.count_stage("size", 0, files.len() + member_sized.len());
+
+    // Files sized like an archive member skip sampling, as members aren't.
+    let candidates = files.len();
+    let sampling = &options.sampling;
+    progress.start_stage(
+        "sample",
+        candidates,
+        total_size(&files, |size| sampling.count * size.min(sampling.size))?,
+    );
+    let files = omit_sample_hash_singletons(files, &options.sampling, progress)?;
+    findings.count_stage("sample", candidates, files.len());
+
+    let candidates = files.len() + member_sized.len();
+    let bytes = total_size(&files, |size| size)? + total_size(&member_sized, |size| size)?;
+    progress.start_stage("hash", candidates, bytes);
+    let mut hash_groups = group_by_hash(files, progress)?;
+    // Their sizes never overlap, so neither do their hashes.
+    hash_groups.extend(group_by(member_sized, |f| get_hash(f, progress))?);
+
+    let mut hash_remaining = 0;
+    let mut identity_remaining = 0;
+    for (hash, files) in hash_groups {
+        let size = get_file_size(&files[0])?;
+        let archived = member_groups.remove(hash.as_str()).unwrap_or_default();
+        if files.len() + archived.len() < 2 {
+            continue;
+        }
+        hash_remaining += files.len();
+
+        let mut identity_groups = group_by(files, |f| get_identity_key(f, &options.identity))?
+            .into_values()
+            .collect::<Vec<Vec<PathBuf>>>();
+        if identity_groups.len() > 1 {
+            identity_groups.iter_mut().for_each(|g| g.sort());
+            identity_groups.sort();
+            findings.splits.push(Split {
+                hash: hash.clone(),
+                groups: identity_groups.clone(),
+            });
+        }
+
+        for g in identity_groups {
+            if g.len() + archived.len() >= 2 {
+                identity_remaining += g.len();
+                let duplicate = Duplicate::with_archived(hash.clone(), size, g, archived.clone());
+                findings.duplicates.push(duplicate);
+            }
+        }
+    }
+
+    findings.count_stage("hash", candidates, hash_remaining);
+    if !matches!(options.identity, Identity::Content) {
+        findings.count_stage("metadata", hash_remaining, identity_remaining);
+    }
+
+    Ok(())
 }
 
-fn omit_head_hash_singletons<I>(files: I) -> io::Result<Vec<PathBuf>>
+/// Omit files of unique size, except those sized like an archive member
+/// which are returned separately as the pair's second.
+fn omit_size_singletons(
+    size_groups: HashMap<u64, Vec<PathBuf>>,
+    member_sizes: &HashSet<u64>,
+) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    let (member_sized, size_groups) = size_groups
+        .into_iter()
+        .partition::<HashMap<_, _>, _>(|(size, _)| member_sizes.contains(size));
+    (ungroup(omit_singletons(size_groups)), ungroup(member_sized))
+}
+
+fn omit_sample_hash_singletons<I>(
+    files: I,
+    sampling: &Sampling,
+    progress: &Progress,
+) -> io::Result<Vec<PathBuf>>
 where
     I: IntoIterator<Item = PathBuf>,
 {
-    let head_hash_groups = group_by(files, |f| get_head_hash(f))?;
-    Ok(ungroup(omit_singletons(head_hash_groups)))
+    let sample_hash_groups = group_by(files, |f| get_sample_hash(f, sampling, progress))?;
+    Ok(ungroup(omit_singletons(sample_hash_groups)))
 }
 
-fn group_by_hash<I>(files: I) -> io::Result<HashMap<String, Vec<PathBuf>>>
+fn group_by_hash<I>(files: I, progress: &Progress) -> io::Result<HashMap<String, Vec<PathBuf>>>
 where
     I: IntoIterator<Item = PathBuf>,
 {
-    let hash_groups = group_by(files, |f| get_hash(f))?;
+    let hash_groups = group_by(files, |f| get_hash(f, progress))?;
     Ok(omit_singletons(hash_groups))
 }
 
@@ -97,37 +375,122 @@ fn ungroup<K>(map: HashMap<K, Vec<PathBuf>>) -> Vec<PathBuf>
 where
     K: Eq,
 {
-    map.into_iter().map(|(_, g)| g).flatten().collect()
+    map.into_values().flatten().collect()
 }
 
-fn get_file_size<P>(path: P) -> io::Result<u64>
+pub fn get_file_size<P>(path: P) -> io::Result<u64>
 where
     P: AsRef<Path>,
 {
     Ok(fs::metadata(path)?.len())
 }
 
-fn get_head_hash<P>(path: P) -> io::Result<String>
+/// Sum of the bytes `read` of each file's size.
+fn total_size<F>(files: &[PathBuf], read: F) -> io::Result<u64>
+where
+    F: Fn(u64) -> u64,
+{
+    let mut total = 0;
+    for f in files {
+        total += read(get_file_size(f)?);
+    }
+
+    Ok(total)
+}
+
+/// Derive a key from the metadata `identity` requires to be identical.
+fn get_identity_key<P>(path: P, identity: &Identity) -> io::Result<String>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    match identity {
+        Identity::Content => Ok(String::new()),
+        Identity::ContentMode => Ok(format!("{:o}", fs::metadata(path)?.mode() & 0o7777)),
+        Identity::ContentOwner => {
+            let metadata = fs::metadata(path)?;
+            Ok(format!("{}:{}", metadata.uid(), metadata.gid()))
+        }
+        Identity::ContentXattrs => {
+            let mut names = xattr::list(path)?.collect::<Vec<_>>();
+            names.sort();
+
+            let mut key = String::new();
+            for name in names {
+                let value = xattr::get(path, &name)?.unwrap_or_default();
+                key.push_str(&hex::encode(name.as_bytes()));
+                key.push('=');
+                key.push_str(&hex::encode(value));
+                key.push(';');
+            }
+
+            Ok(key)
+        }
+    }
+}
+
+/// Hash `sampling.count` blocks evenly spaced from start to end of a file.
+fn get_sample_hash<P>(path: P, sampling: &Sampling, progress: &Progress) -> io::Result<String>
+where
+    P: AsRef<Path>,
+{
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let last_offset = size.saturating_sub(sampling.size);
+
+    // Files smaller than a block are read whole, so need no bigger buffer.
+    let mut buf = vec![0; sampling.size.min(size) as usize];
+    let mut hasher = Hasher::new(HASH_ALGORITHM);
+    // A file no bigger than a block is read whole by its first sample.
+    let count = if size <= sampling.size {
+        1
+    } else {
+        sampling.count
+    };
+    for i in 0..count {
+        let offset = match count {
+            1 => 0,
+            count => last_offset * i / (count - 1),
+        };
+        file.seek(SeekFrom::Start(offset))?;
+
+        let mut count = 0;
+        while count < buf.len() {
+            match file.read(&mut buf[count..])? {
+                0 => break,
+                read => count += read,
+            }
+        }
+        hasher.write_all(&buf[..count])?;
+        progress.hashed(count as u64);
+    }
+
+    Ok(hex::encode(hasher.finish()))
+}
+
+/// Hash the whole content of a file, as duplicates are identified by.
+pub fn hash_file<P>(path: P) -> io::Result<String>
 where
     P: AsRef<Path>,
 {
-    let mut buf = [0; HEAD_SIZE];
-    let count = File::open(path)?.read(&mut buf)?;
-    Ok(crypto_hash::hex_digest(HASH_ALGORITHM, &buf[..count]))
+    get_hash(path, &Progress::hidden())
 }
 
-fn get_hash<P>(path: P) -> io::Result<String>
+fn get_hash<P>(path: P, progress: &Progress) -> io::Result<String>
 where
     P: AsRef<Path>,
 {
-    let mut buf = [0; HEAD_SIZE];
+    let mut buf = [0; READ_SIZE];
     let mut hasher = Hasher::new(HASH_ALGORITHM);
 
     let mut reader = BufReader::with_capacity(FILE_BUFFER_SIZE, File::open(path)?);
     loop {
         match reader.read(&mut buf)? {
             0 => break,
-            count => hasher.write(&buf[..count])?,
+            count => {
+                hasher.write_all(&buf[..count])?;
+                progress.hashed(count as u64);
+            }
         };
     }
 
@@ -136,17 +499,23 @@ where
 
 #[cfg(test)]
 mod tests {
-    use crate::find::{self, Duplicate};
+    use crate::archive::Member;
+    use crate::find::{self, Duplicate, Identity, Options, Sampling};
     use rand::{self, Rng};
     use std::collections::HashSet;
+    use std::fs::{self, Permissions};
     use std::io::{self, Seek, SeekFrom, Write};
+    use std::os::unix::fs::PermissionsExt;
     use std::path::{Path, PathBuf};
     use tempfile::{self, NamedTempFile};
 
+    const CONTENT_SIZE: usize = 4_000;
+
     #[test]
     fn duplicate_new() {
         let dup = Duplicate::new(
             "Hello".to_string(),
+            5,
             vec![
                 Path::new("Hello").to_path_buf(),
                 Path::new("World").to_path_buf(),
@@ -154,6 +523,8 @@ mod tests {
         );
 
         assert_eq!(dup.hash, "Hello");
+        assert_eq!(dup.size, 5);
+        assert_eq!(dup.wasted_bytes(), 5);
         assert_eq!(
             dup.files,
             vec![
@@ -166,15 +537,19 @@ mod tests {
     #[test]
     #[should_panic]
     fn duplicate_new_invalid_files() {
-        Duplicate::new("Hello".to_string(), vec![Path::new("Hello").to_path_buf()]);
+        Duplicate::new(
+            "Hello".to_string(),
+            5,
+            vec![Path::new("Hello").to_path_buf()],
+        );
     }
 
     #[test]
     fn find_duplicate_files() {
-        let head = random_bytes(find::HEAD_SIZE);
-        let body1 = random_bytes(find::HEAD_SIZE);
-        let body2 = random_bytes(find::HEAD_SIZE);
-        let body3 = random_bytes(find::HEAD_SIZE);
+        let head = random_bytes(CONTENT_SIZE);
+        let body1 = random_bytes(CONTENT_SIZE);
+        let body2 = random_bytes(CONTENT_SIZE);
+        let body3 = random_bytes(CONTENT_SIZE);
 
         let _zero1 = temp_file(&[]).unwrap();
         let _zero2 = temp_file(&[]).unwrap();
@@ -182,8 +557,8 @@ mod tests {
         let _same_head2 = temp_file(&combine(&head, &body2)).unwrap();
         let same_content1 = temp_file(&combine(&head, &body3)).unwrap();
         let same_content2 = temp_file(&combine(&head, &body3)).unwrap();
-        let _random1 = temp_file(&random_bytes(find::HEAD_SIZE * 2)).unwrap();
-        let _random2 = temp_file(&random_bytes(find::HEAD_SIZE * 3)).unwrap();
+        let _random1 = temp_file(&random_bytes(CONTENT_SIZE * 2)).unwrap();
+        let _random2 = temp_file(&random_bytes(CONTENT_SIZE * 3)).unwrap();
         let files = vec![
             &_zero1,
             &_zero2,
@@ -195,18 +570,117 @@ mod tests {
             &_random2,
         ];
 
-        let duplicates = find::duplicate_files(files).unwrap();
-        assert_eq!(duplicates.len(), 1);
+        let spilling = Options {
+            memory_limit: Some(1),
+            ..Options::default()
+        };
+        for options in &[Options::default(), spilling] {
+            let findings = find::duplicate_files(files.clone(), options, &[]).unwrap();
+            let duplicates = findings.duplicates;
+            assert_eq!(duplicates.len(), 1);
+            assert!(findings.splits.is_empty());
+            assert_eq!(findings.stages[0].candidates, 8);
+            assert_eq!(findings.stages[0].remaining, 5);
+
+            let duplicate_paths = &duplicates.first().unwrap().files;
+            assert_eq!(duplicate_paths.len(), 2);
+            assert!(unordered_eq(
+                duplicate_paths,
+                &[
+                    same_content1.path().to_path_buf(),
+                    same_content2.path().to_path_buf()
+                ]
+            ));
+        }
+    }
 
-        let duplicate_paths = &duplicates.first().unwrap().files;
-        assert_eq!(duplicate_paths.len(), 2);
+    #[test]
+    fn find_duplicate_files_sampled() {
+        let start = random_bytes(CONTENT_SIZE);
+        let end = random_bytes(CONTENT_SIZE);
+        let middle1 = random_bytes(CONTENT_SIZE);
+        let middle2 = random_bytes(CONTENT_SIZE);
+
+        let same_ends1 = temp_file(&combine(&combine(&start, &middle1), &end)).unwrap();
+        let same_ends2 = temp_file(&combine(&combine(&start, &middle2), &end)).unwrap();
+        let files = vec![&same_ends1, &same_ends2];
+
+        let options = Options {
+            sampling: Sampling {
+                count: 2,
+                size: CONTENT_SIZE as u64,
+            },
+            ..Options::default()
+        };
+        let findings = find::duplicate_files(files.clone(), &options, &[]).unwrap();
+        assert!(findings.duplicates.is_empty());
+        assert_eq!(findings.stages[1].name, "sample");
+        assert_eq!(findings.stages[1].remaining, 2);
+
+        let findings = find::duplicate_files(files, &Options::default(), &[]).unwrap();
+        assert!(findings.duplicates.is_empty());
+        assert_eq!(findings.stages[1].remaining, 0);
+    }
+
+    #[test]
+    fn find_duplicate_files_split_by_mode() {
+        let content = random_bytes(CONTENT_SIZE);
+        let executable1 = temp_file(&content).unwrap();
+        let executable2 = temp_file(&content).unwrap();
+        let plain = temp_file(&content).unwrap();
+        for (f, mode) in [
+            (&executable1, 0o755),
+            (&executable2, 0o755),
+            (&plain, 0o644),
+        ] {
+            fs::set_permissions(f.path(), Permissions::from_mode(mode)).unwrap();
+        }
+        let files = vec![&executable1, &executable2, &plain];
+
+        let findings = find::duplicate_files(files.clone(), &Options::default(), &[]).unwrap();
+        assert_eq!(findings.duplicates.len(), 1);
+        assert_eq!(findings.duplicates[0].files.len(), 3);
+
+        let options = Options {
+            identity: Identity::ContentMode,
+            ..Options::default()
+        };
+        let findings = find::duplicate_files(files, &options, &[]).unwrap();
+        assert_eq!(findings.duplicates.len(), 1);
         assert!(unordered_eq(
-            &duplicate_paths,
+            &findings.duplicates[0].files,
             &[
-                same_content1.path().to_path_buf(),
-                same_content2.path().to_path_buf()
+                executable1.path().to_path_buf(),
+                executable2.path().to_path_buf()
             ]
         ));
+        assert_eq!(findings.splits.len(), 1);
+        assert_eq!(findings.splits[0].groups.len(), 2);
+    }
+
+    #[test]
+    fn find_duplicate_files_archived() {
+        let content = random_bytes(CONTENT_SIZE);
+        let loose = temp_file(&content).unwrap();
+        let _unique = temp_file(&random_bytes(CONTENT_SIZE)).unwrap();
+        let member = Member {
+            path: Path::new("backup.zip!/loose").to_path_buf(),
+            size: content.len() as u64,
+            hash: crypto_hash::hex_digest(find::HASH_ALGORITHM, &content),
+        };
+
+        let findings =
+            find::duplicate_files(vec![&loose, &_unique], &Options::default(), &[member]).unwrap();
+
+        assert_eq!(findings.duplicates.len(), 1);
+        assert_eq!(
+            findings.duplicates[0].files,
+            vec![loose.path().to_path_buf()]
+        );
+        assert_eq!(
+            findings.duplicates[0].archived,
+            vec![Path::new("backup.zip!/loose").to_path_buf()]
+        );
     }
 
     fn unordered_eq<P>(first: &[P], second: &[P]) -> bool
@@ -214,11 +688,11 @@ mod tests {
         P: AsRef<Path>,
     {
         let first = first
-            .into_iter()
+            .iter()
             .map(|p| p.as_ref().to_path_buf())
             .collect::<HashSet<PathBuf>>();
         let second = second
-            .into_iter()
+            .iter()
             .map(|p| p.as_ref().to_path_buf())
             .collect::<HashSet<PathBuf>>();
 
@@ -227,8 +701,8 @@ mod tests {
 
     fn combine(first: &[u8], second: &[u8]) -> Vec<u8> {
         let mut combined = Vec::with_capacity(first.len() + second.len());
-        combined.extend(first.iter().map(|b| b.clone()));
-        combined.extend(second.iter().map(|b| b.clone()));
+        combined.extend(first.iter().copied());
+        combined.extend(second.iter().copied());
 
         combined
     }
@@ -237,7 +711,7 @@ mod tests {
         let mut named_file = NamedTempFile::new()?;
         let file = named_file.as_file_mut();
 
-        file.write(content)?;
+        file.write_all(content)?;
         file.flush()?;
         file.sync_all()?; // Sync metadata with file content changes.
         file.seek(SeekFrom::Start(0))?;
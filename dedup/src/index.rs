@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use tempfile::TempDir;
+
+/// Number of temporary files spilled size groups are spread over, each of
+/// which is loaded on its own.
+const SPILL_PARTITIONS: usize = 64;
+/// Rough per file cost of an in memory entry besides its name.
+const ENTRY_OVERHEAD: u64 = 64;
+/// Rough per directory cost of an interned directory besides its path.
+const DIR_OVERHEAD: u64 = 64;
+
+/// A file stored compactly as its interned parent directory and its name.
+struct Entry {
+    dir: u32,
+    name: Box<[u8]>,
+}
+
+/// Files grouped by size, bounded in memory by spilling groups to temporary
+/// files partitioned by size, so all files of a size always end up together.
+///
+/// Interned directories are never spilled, as spilled entries refer to them,
+/// but count towards the memory limit along with entries.
+pub struct SizeIndex {
+    dirs: Vec<Rc<Path>>,
+    dir_ids: HashMap<Rc<Path>, u32>,
+    dirs_memory: u64,
+    groups: HashMap<u64, Vec<Entry>>,
+    memory: u64,
+    memory_limit: Option<u64>,
+    spill: Option<Spill>,
+}
+
+struct Spill {
+    _dir: TempDir,
+    partitions: Vec<BufWriter<File>>,
+}
+
+impl SizeIndex {
+    /// Create an index that spills once its entries take about
+    /// `memory_limit` bytes, or never if `None`.
+    pub fn new(memory_limit: Option<u64>) -> SizeIndex {
+        SizeIndex {
+            dirs: vec![],
+            dir_ids: HashMap::new(),
+            dirs_memory: 0,
+            groups: HashMap::new(),
+            memory: 0,
+            memory_limit,
+            spill: None,
+        }
+    }
+
+    pub fn insert(&mut self, path: &Path, size: u64) -> io::Result<()> {
+        let dir = self.intern(path.parent().unwrap_or_else(|| Path::new("")));
+        let name = Box::<[u8]>::from(path.file_name().map(|n| n.as_bytes()).unwrap_or_default());
+
+        self.memory += ENTRY_OVERHEAD + name.len() as u64;
+        self.groups
+            .entry(size)
+            .or_default()
+            .push(Entry { dir, name });
+
+        match self.memory_limit {
+            Some(limit) if self.memory + self.dirs_memory > limit => self.spill(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Call `f` with the size groups of one partition at a time, or of all
+    /// files at once if nothing was spilled.
+    pub fn for_each_partition<F>(mut self, mut f: F) -> io::Result<()>
+    where
+        F: FnMut(HashMap<u64, Vec<PathBuf>>) -> io::Result<()>,
+    {
+        if self.spill.is_none() {
+            let groups = mem::take(&mut self.groups);
+            return f(self.resolve(groups));
+        }
+
+        self.spill()?;
+        let spill = self.spill.take().unwrap();
+        for partition in spill.partitions {
+            let mut file = partition.into_inner().map_err(|err| err.into_error())?;
+            file.seek(SeekFrom::Start(0))?;
+            let groups = read_partition(BufReader::new(file))?;
+            f(self.resolve(groups))?;
+        }
+
+        Ok(())
+    }
+
+    fn intern(&mut self, dir: &Path) -> u32 {
+        if let Some(&id) = self.dir_ids.get(dir) {
+            return id;
+        }
+
+        let id = self.dirs.len() as u32;
+        self.dirs_memory += DIR_OVERHEAD + dir.as_os_str().len() as u64;
+        let dir = Rc::<Path>::from(dir);
+        self.dirs.push(dir.clone());
+        self.dir_ids.insert(dir, id);
+        id
+    }
+
+    /// Append all in memory groups to their partition's temporary file.
+    fn spill(&mut self) -> io::Result<()> {
+        if self.spill.is_none() {
+            let dir = tempfile::tempdir()?;
+            let partitions = (0..SPILL_PARTITIONS)
+                .map(|i| Ok(BufWriter::new(spill_file(&dir, i)?)))
+                .collect::<io::Result<Vec<BufWriter<File>>>>()?;
+            self.spill = Some(Spill {
+                _dir: dir,
+                partitions,
+            });
+        }
+
+        let partitions = &mut self.spill.as_mut().unwrap().partitions;
+        for (size, entries) in self.groups.drain() {
+            let out = &mut partitions[partition_of(size)];
+            for e in entries {
+                out.write_all(&size.to_le_bytes())?;
+                out.write_all(&e.dir.to_le_bytes())?;
+                out.write_all(&(e.name.len() as u32).to_le_bytes())?;
+                out.write_all(&e.name)?;
+            }
+        }
+        self.memory = 0;
+
+        Ok(())
+    }
+
+    fn resolve(&self, groups: HashMap<u64, Vec<Entry>>) -> HashMap<u64, Vec<PathBuf>> {
+        groups
+            .into_iter()
+            .map(|(size, entries)| {
+                let paths = entries
+                    .into_iter()
+                    .map(|e| self.dirs[e.dir as usize].join(OsStr::from_bytes(&e.name)))
+                    .collect();
+                (size, paths)
+            })
+            .collect()
+    }
+}
+
+fn spill_file(dir: &TempDir, partition: usize) -> io::Result<File> {
+    File::options()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(dir.path().join(partition.to_string()))
+}
+
+/// Spread sizes over partitions, which plain modulo wouldn't do for the
+/// common multiples of block sizes.
+fn partition_of(size: u64) -> usize {
+    (size.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 58) as usize % SPILL_PARTITIONS
+}
+
+fn read_partition<R>(mut reader: R) -> io::Result<HashMap<u64, Vec<Entry>>>
+where
+    R: Read,
+{
+    let mut groups = HashMap::<u64, Vec<Entry>>::new();
+    let mut size = [0; 8];
+    loop {
+        match reader.read_exact(&mut size) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err),
+        }
+
+        let mut dir = [0; 4];
+        reader.read_exact(&mut dir)?;
+        let mut length = [0; 4];
+        reader.read_exact(&mut length)?;
+        let mut name = vec![0; u32::from_le_bytes(length) as usize];
+        reader.read_exact(&mut name)?;
+
+        groups
+            .entry(u64::from_le_bytes(size))
+            .or_default()
+            .push(Entry {
+                dir: u32::from_le_bytes(dir),
+                name: name.into(),
+            });
+    }
+
+    Ok(groups)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index::SizeIndex;
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn spill_size_groups() {
+        let files = (0..1_000)
+            .map(|i| {
+                (
+                    Path::new("dir")
+                        .join((i % 7).to_string())
+                        .join(i.to_string()),
+                    i % 13,
+                )
+            })
+            .collect::<Vec<(PathBuf, u64)>>();
+
+        let mut in_memory = SizeIndex::new(None);
+        let mut spilled = SizeIndex::new(Some(1_000));
+        for (path, size) in &files {
+            in_memory.insert(path, *size).unwrap();
+            spilled.insert(path, *size).unwrap();
+        }
+        assert!(spilled.spill.is_some());
+
+        let collect = |index: SizeIndex| {
+            let mut all = HashMap::new();
+            index
+                .for_each_partition(|groups| {
+                    for (size, mut paths) in groups {
+                        paths.sort();
+                        assert!(all.insert(size, paths).is_none());
+                    }
+                    Ok(())
+                })
+                .unwrap();
+            all
+        };
+        let in_memory = collect(in_memory);
+        assert_eq!(in_memory.len(), 13);
+        assert_eq!(in_memory, collect(spilled));
+    }
+
+    #[test]
+    fn count_dirs_towards_limit() {
+        let mut index = SizeIndex::new(Some(2_000));
+        for i in 0..20 {
+            let dir = Path::new("dir").join("d".repeat(100)).join(i.to_string());
+            index.insert(&dir.join("f"), 1).unwrap();
+        }
+
+        assert!(index.spill.is_some());
+    }
+}
@@ -1,3 +1,4 @@
+use crate::similar;
 use std::fs;
 use std::io;
 use std::path::{Component, Path, PathBuf};
@@ -9,6 +10,7 @@ pub enum KeepCriteria {
     Newest,
     Shallowest,
     Deepest,
+    HighestResolution,
 }
 
 pub fn by_criteria<P>(duplicate_files: &[P], criteria: &KeepCriteria) -> io::Result<PathBuf>
@@ -16,7 +18,7 @@ where
     P: AsRef<Path>,
 {
     let duplicate_files = duplicate_files
-        .into_iter()
+        .iter()
         .map(|p| p.as_ref().to_path_buf())
         .collect::<Vec<PathBuf>>();
 
@@ -25,6 +27,7 @@ where
         KeepCriteria::Newest => keep_newest(duplicate_files),
         KeepCriteria::Shallowest => keep_shallowest(duplicate_files),
         KeepCriteria::Deepest => keep_deepest(duplicate_files),
+        KeepCriteria::HighestResolution => keep_highest_resolution(duplicate_files),
     }
 }
 
@@ -39,14 +42,14 @@ fn keep_newest(files: Vec<PathBuf>) -> io::Result<PathBuf> {
 fn sort_by_mod_time(files: Vec<PathBuf>) -> io::Result<Vec<PathBuf>> {
     let mod_times = files
         .iter()
-        .map(|f| get_modification_time(f))
+        .map(get_modification_time)
         .collect::<io::Result<Vec<SystemTime>>>();
     let mut mod_times_files = mod_times?
         .into_iter()
         .zip(files)
         .collect::<Vec<(SystemTime, PathBuf)>>();
 
-    mod_times_files.sort_by(|a, b| a.0.cmp(&b.0));
+    mod_times_files.sort_by_key(|a| a.0);
 
     Ok(mod_times_files.into_iter().map(|(_, f)| f).collect())
 }
@@ -69,14 +72,14 @@ fn keep_deepest(files: Vec<PathBuf>) -> io::Result<PathBuf> {
 fn sort_by_path_depth(files: Vec<PathBuf>) -> io::Result<Vec<PathBuf>> {
     let depths = files
         .iter()
-        .map(|f| get_path_depth(f))
+        .map(get_path_depth)
         .collect::<io::Result<Vec<usize>>>()?;
     let mut depths_files = depths
         .into_iter()
         .zip(files)
         .collect::<Vec<(usize, PathBuf)>>();
 
-    depths_files.sort_by(|a, b| a.0.cmp(&b.0));
+    depths_files.sort_by_key(|a| a.0);
 
     Ok(depths_files.into_iter().map(|(_, f)| f).collect())
 }
@@ -93,6 +96,29 @@ where
         .len())
 }
 
+fn keep_highest_resolution(files: Vec<PathBuf>) -> io::Result<PathBuf> {
+    Ok(sort_by_resolution(files)?.last().unwrap().clone())
+}
+
+/// Sort images by pixel count, breaking ties by file size.
+fn sort_by_resolution(files: Vec<PathBuf>) -> io::Result<Vec<PathBuf>> {
+    let resolutions = files
+        .iter()
+        .map(|f| {
+            let (width, height) = similar::get_resolution(f)?;
+            Ok((width as u64 * height as u64, fs::metadata(f)?.len()))
+        })
+        .collect::<io::Result<Vec<(u64, u64)>>>()?;
+    let mut resolutions_files = resolutions
+        .into_iter()
+        .zip(files)
+        .collect::<Vec<((u64, u64), PathBuf)>>();
+
+    resolutions_files.sort_by_key(|a| a.0);
+
+    Ok(resolutions_files.into_iter().map(|(_, f)| f).collect())
+}
+
 #[cfg(test)]
 mod tests {
     use crate::keep::{self, KeepCriteria};
@@ -1,42 +1,461 @@
+mod archive;
 mod cli;
 mod filesystem;
 mod find;
+mod index;
 mod keep;
+mod metadata;
+mod overlap;
+mod progress;
+mod prune;
+mod report;
+mod script;
+mod similar;
+mod summary;
+mod tree;
+mod watch;
 
-use std::fs;
-use std::io;
+use cli::Args;
+use find::{Duplicate, Findings, Sampling, Split, Stage};
+use progress::Progress;
+use script::{Script, Shell};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::iter;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use summary::Summary;
+use watch::Watcher;
 
 fn main() -> io::Result<()> {
     let args = cli::get_args();
 
-    let mut files = vec![];
-    for p in args.paths {
-        if p.is_dir() {
-            files.extend(filesystem::walk_files(p)?)
+    if args.watch {
+        return watch(&args);
+    }
+
+    if let Some(threshold) = args.overlap {
+        return print_overlaps(&collect_files(&args)?, threshold);
+    }
+
+    let findings = if args.dirs {
+        Findings {
+            duplicates: tree::duplicate_dirs(&input_dirs(&args), &args.keep_criteria)?,
+            ..Findings::default()
+        }
+    } else {
+        if args.similar_images {
+            let files = collect_files(&args)?;
+            Findings {
+                duplicates: similar::similar_images(&files, &args.image_hash, args.max_distance)?,
+                ..Findings::default()
+            }
         } else {
-            files.push(p)
+            let progress = Progress::new(args.progress);
+            let findings =
+                find::duplicates_in(walk_paths(&args)?, &find_options(&args), &[], &progress)?;
+            if args.stats {
+                print_stages(&findings.stages);
+            }
+
+            findings
+        }
+    };
+
+    if args.report_only {
+        print_splits(&mut io::stdout(), &findings.splits)?;
+        return print_report(findings.duplicates, &args);
+    }
+
+    match args.emit_script {
+        Some(shell) => {
+            print_splits(&mut io::stderr(), &findings.splits)?;
+            let summary = emit_script(findings.duplicates, &args, shell)?;
+            summary.write(&mut io::stderr(), args.top, true)
+        }
+        None if args.print0 => {
+            print_splits(&mut io::stderr(), &findings.splits)?;
+            let summary = print0(findings.duplicates, &args)?;
+            summary.write(&mut io::stderr(), args.top, true)
+        }
+        None => {
+            print_splits(&mut io::stdout(), &findings.splits)?;
+            let summary = delete_duplicates(findings.duplicates, &args)?;
+            summary.write(&mut io::stdout(), args.top, args.dry_run)
+        }
+    }
+}
+
+/// Delete duplicates of files as they're written to the input directories,
+/// summarizing each batch of events that formed any.
+fn watch(args: &Args) -> io::Result<()> {
+    let mut watcher = Watcher::new(&input_dirs(args))?;
+    loop {
+        let mut summary = Summary::default();
+        for mut d in watcher.wait()? {
+            // Earlier groups of the same events may have deleted some files.
+            d.files.retain(|f| f.is_file());
+            if d.files.len() < 2 {
+                continue;
+            }
+
+            match delete_duplicates(vec![d], args) {
+                Ok(deleted) => summary.merge(deleted),
+                Err(error) => {
+                    eprintln!("Failed to delete duplicates: {}", error);
+                    summary.error();
+                }
+            }
+        }
+
+        if !summary.is_empty() {
+            summary.write(&mut io::stdout(), args.top, args.dry_run)?;
+        }
+    }
+}
+
+fn collect_files(args: &Args) -> io::Result<Vec<PathBuf>> {
+    walk_paths(args)?.collect()
+}
+
+/// Stream files listed by `--files-from`, or else files of all paths, walking
+/// those that are directories.
+fn walk_paths(args: &Args) -> io::Result<Box<dyn Iterator<Item = io::Result<PathBuf>> + '_>> {
+    let separator = if args.null { b'\0' } else { b'\n' };
+    match &args.files_from {
+        Some(list) if list.as_os_str() == "-" => Ok(Box::new(filesystem::read_file_list(
+            io::stdin().lock(),
+            separator,
+        ))),
+        Some(list) => Ok(Box::new(filesystem::read_file_list(
+            BufReader::new(File::open(list)?),
+            separator,
+        ))),
+        None => Ok(Box::new(args.paths.iter().flat_map(
+            |p| -> Box<dyn Iterator<Item = io::Result<PathBuf>>> {
+                if p.is_dir() {
+                    Box::new(filesystem::walk(p))
+                } else {
+                    Box::new(iter::once(Ok(p.clone())))
+                }
+            },
+        ))),
+    }
+}
+
+fn find_options(args: &Args) -> find::Options {
+    let size = args.sample_size.unwrap_or_else(|| {
+        args.paths
+            .first()
+            .and_then(|p| filesystem::block_size(p).ok())
+            .filter(|&s| s > 0 && s <= find::MAX_SAMPLE_SIZE)
+            .unwrap_or(Sampling::default().size)
+    });
+
+    find::Options {
+        identity: args.identity.clone(),
+        sampling: Sampling {
+            count: args.samples,
+            size,
+        },
+        memory_limit: args.memory_limit,
+        scan_archives: args.scan_archives,
+    }
+}
+
+fn print_overlaps(files: &[PathBuf], threshold: f64) -> io::Result<()> {
+    for o in overlap::overlapping_files(files, threshold)? {
+        println!(
+            "{:.2} similar, {} bytes shared",
+            o.similarity, o.shared_bytes
+        );
+        println!("\t{}", o.files.0.display());
+        println!("\t{}", o.files.1.display());
+    }
+
+    Ok(())
+}
+
+fn print_stages(stages: &[Stage]) {
+    for s in stages {
+        eprintln!(
+            "{} stage: {} of {} candidates remaining, {} eliminated",
+            s.name,
+            s.remaining,
+            s.candidates,
+            s.candidates - s.remaining
+        );
+    }
+}
+
+/// List duplicates without choosing what to keep.
+fn print_report(duplicates: Vec<Duplicate>, args: &Args) -> io::Result<()> {
+    let duplicates = report::select(
+        duplicates,
+        &args.sort,
+        args.min_copies,
+        args.touching.as_deref(),
+    );
+
+    let mut wasted = 0;
+    for d in &duplicates {
+        let group_wasted = wasted_bytes(d, args)?;
+        wasted += group_wasted;
+        println!("{}", d.hash);
+        println!(
+            "\t{} copies of {} bytes, {} bytes wasted",
+            report::copies(d),
+            d.size,
+            group_wasted
+        );
+        for f in &d.files {
+            println!("\t{}", describe(f, args));
+        }
+        for m in &d.archived {
+            println!("\t{}", m.display());
+        }
+    }
+    println!("{} groups, {} bytes wasted", duplicates.len(), wasted);
+
+    Ok(())
+}
+
+/// Report files with identical content that differ in required metadata.
+fn print_splits<W>(out: &mut W, splits: &[Split]) -> io::Result<()>
+where
+    W: Write,
+{
+    for s in splits {
+        writeln!(out, "{}", s.hash)?;
+        writeln!(out, "\tSplit by metadata into {} groups", s.groups.len())?;
+        for (i, g) in s.groups.iter().enumerate() {
+            for f in g {
+                writeln!(out, "\tGroup {} {}", i + 1, f.display())?;
+            }
         }
     }
 
-    let duplicates = find::duplicate_files(files)?;
+    Ok(())
+}
+
+fn delete_duplicates(duplicates: Vec<Duplicate>, args: &Args) -> io::Result<Summary> {
+    let mut summary = Summary::default();
+    let mut deleted = vec![];
     for d in duplicates {
         println!("{}", &d.hash);
+        summary.group();
 
-        let kept = keep::by_criteria(&d.files, &args.keep_criteria)?;
-        let for_deletion = d.files.into_iter().filter(|f| *f != kept);
+        let size = d.size;
+        let (kept, for_deletion) = choose_kept(d, args)?;
+        let wasted = for_deletion
+            .iter()
+            .map(|f| redundant_size(f, size, args))
+            .sum::<u64>();
+        println!("\tWasted {} bytes", wasted);
+        let kept = match kept {
+            Kept::File(kept) => kept,
+            Kept::Archived(members) => {
+                for m in members {
+                    println!("\tKept {}", m.display());
+                }
+                delete_files(for_deletion, size, &mut deleted, &mut summary, args);
+                continue;
+            }
+        };
 
-        println!("\tKept {}", kept.display());
-        for f in for_deletion {
+        println!("\tKept {}", describe(&kept, args));
+        if !args.merge_metadata.is_empty() {
             if !args.dry_run {
-                match fs::remove_file(&f) {
-                    Ok(()) => println!("\tDeleted {}", f.display()),
-                    Err(error) => println!("\tFailed to delete {}: {}", &f.display(), error),
+                if let Err(error) = metadata::merge(&kept, &for_deletion, &args.merge_metadata) {
+                    println!(
+                        "\tFailed to merge metadata onto {}: {}",
+                        kept.display(),
+                        error
+                    );
+                    summary.error();
+                    continue;
                 }
-            } else {
-                println!("\tDeleted {}", f.display());
             }
+            println!("\tMerged metadata onto {}", kept.display());
+        }
+        delete_files(for_deletion, size, &mut deleted, &mut summary, args);
+    }
+
+    if args.prune_empty_dirs {
+        prune_empty_dirs(&deleted, &mut summary, args)?;
+    }
+
+    Ok(summary)
+}
+
+/// Delete redundant files of a duplicate of `size` bytes, appending those
+/// deleted to `deleted`.
+fn delete_files(
+    for_deletion: Vec<PathBuf>,
+    size: u64,
+    deleted: &mut Vec<PathBuf>,
+    summary: &mut Summary,
+    args: &Args,
+) {
+    for f in for_deletion {
+        let description = describe(&f, args);
+        let size = redundant_size(&f, size, args);
+        if !args.dry_run {
+            match remove(&f) {
+                Ok(()) => println!("\tDeleted {}", description),
+                Err(error) => {
+                    println!("\tFailed to delete {}: {}", &f.display(), error);
+                    summary.error();
+                    continue;
+                }
+            }
+        } else {
+            println!("\tDeleted {}", description);
+        }
+
+        summary.removed(&f, size);
+        deleted.push(f);
+    }
+}
+
+fn prune_empty_dirs(deleted: &[PathBuf], summary: &mut Summary, args: &Args) -> io::Result<()> {
+    let empty_dirs = prune::empty_dirs(deleted, &input_dirs(args))?;
+    if empty_dirs.is_empty() {
+        return Ok(());
+    }
+
+    println!("Empty directories");
+    for dir in empty_dirs {
+        if !args.dry_run {
+            match fs::remove_dir(&dir) {
+                Ok(()) => println!("\tPruned {}", dir.display()),
+                Err(error) => {
+                    println!("\tFailed to prune {}: {}", dir.display(), error);
+                    summary.error();
+                }
+            }
+        } else {
+            println!("\tPruned {}", dir.display());
         }
     }
 
     Ok(())
 }
+
+fn emit_script(duplicates: Vec<Duplicate>, args: &Args, shell: Shell) -> io::Result<Summary> {
+    let mut summary = Summary::default();
+    let mut script = Script::new(io::stdout(), shell)?;
+    let mut deleted = vec![];
+    for d in duplicates {
+        summary.group();
+        let hash = d.hash.clone();
+        let size = d.size;
+        let (kept, for_deletion) = choose_kept(d, args)?;
+        match kept {
+            Kept::File(kept) => script.remove(&hash, &kept, &for_deletion, args.similar_images)?,
+            Kept::Archived(members) => {
+                script.remove_archived(&hash, size, &members, &for_deletion)?
+            }
+        }
+        for f in &for_deletion {
+            summary.removed(f, redundant_size(f, size, args));
+        }
+        deleted.extend(for_deletion);
+    }
+
+    if args.prune_empty_dirs {
+        script.prune(&prune::empty_dirs(&deleted, &input_dirs(args))?)?;
+    }
+
+    Ok(summary)
+}
+
+/// Write paths to delete, each terminated by NUL for `xargs -0`.
+fn print0(duplicates: Vec<Duplicate>, args: &Args) -> io::Result<Summary> {
+    let mut summary = Summary::default();
+    let mut out = BufWriter::new(io::stdout().lock());
+    for d in duplicates {
+        summary.group();
+        let size = d.size;
+        let (_, for_deletion) = choose_kept(d, args)?;
+        for f in for_deletion {
+            summary.removed(&f, redundant_size(&f, size, args));
+            out.write_all(f.as_os_str().as_bytes())?;
+            out.write_all(b"\0")?;
+        }
+    }
+    out.flush()?;
+
+    Ok(summary)
+}
+
+/// Bytes freed by removing `path` from a duplicate of `size` bytes, which
+/// for similar images differs per file.
+fn redundant_size(path: &Path, size: u64, args: &Args) -> u64 {
+    if !args.similar_images {
+        return size;
+    }
+
+    find::get_file_size(path).unwrap_or(size)
+}
+
+/// Bytes taken by redundant copies of a duplicate, which for similar images
+/// are the sizes of all but the file that would be kept.
+fn wasted_bytes(d: &Duplicate, args: &Args) -> io::Result<u64> {
+    if !args.similar_images || !d.archived.is_empty() {
+        return Ok(d.wasted_bytes());
+    }
+
+    let kept = keep::by_criteria(&d.files, &args.keep_criteria)?;
+    Ok(d.files
+        .iter()
+        .filter(|f| **f != kept)
+        .map(|f| redundant_size(f, d.size, args))
+        .sum())
+}
+
+enum Kept {
+    File(PathBuf),
+    Archived(Vec<PathBuf>),
+}
+
+/// Choose what to keep of a duplicate, returning it along with the rest.
+///
+/// Archive members are never deleted, so if there are any they are what's
+/// kept and all files are redundant.
+fn choose_kept(d: Duplicate, args: &Args) -> io::Result<(Kept, Vec<PathBuf>)> {
+    if !d.archived.is_empty() {
+        return Ok((Kept::Archived(d.archived), d.files));
+    }
+
+    let kept = keep::by_criteria(&d.files, &args.keep_criteria)?;
+    let for_deletion = d.files.into_iter().filter(|f| *f != kept).collect();
+
+    Ok((Kept::File(kept), for_deletion))
+}
+
+/// Display a path, along with resolution and size for similar images.
+fn describe(path: &Path, args: &Args) -> String {
+    if !args.similar_images {
+        return path.display().to_string();
+    }
+
+    match similar::describe(path) {
+        Ok(description) => format!("{} ({})", path.display(), description),
+        Err(_) => path.display().to_string(),
+    }
+}
+
+/// Directories given as input, which are never deleted themselves.
+fn input_dirs(args: &Args) -> Vec<PathBuf> {
+    args.paths.iter().filter(|p| p.is_dir()).cloned().collect()
+}
+
+/// Remove a file, or a whole directory tree.
+fn remove(path: &Path) -> io::Result<()> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
@@ -0,0 +1,160 @@
+use filetime::{self, FileTime};
+use std::collections::HashSet;
+use std::fs::{self, Permissions};
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+const PERMISSION_BITS: u32 = 0o777;
+
+#[derive(Debug, Default)]
+pub struct MergeOptions {
+    pub mtime: bool,
+    pub atime: bool,
+    pub xattrs: bool,
+    pub mode: Option<ModeMerge>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ModeMerge {
+    Permissive,
+    Restrictive,
+}
+
+impl MergeOptions {
+    pub fn is_empty(&self) -> bool {
+        !self.mtime && !self.atime && !self.xattrs && self.mode.is_none()
+    }
+}
+
+/// Merge metadata of `removed` duplicates onto `kept` before their deletion.
+///
+/// Times are set to the earliest among all files, extended attributes missing
+/// from `kept` are copied over and permission bits are either unioned or
+/// intersected. Existing attributes of `kept` are never overwritten.
+pub fn merge<P, Q>(kept: P, removed: &[Q], options: &MergeOptions) -> io::Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let kept = kept.as_ref();
+
+    if options.xattrs {
+        merge_xattrs(kept, removed)?;
+    }
+    if let Some(mode) = &options.mode {
+        merge_mode(kept, removed, mode)?;
+    }
+    // Times go last since other changes may touch them.
+    if options.mtime || options.atime {
+        merge_times(kept, removed, options)?;
+    }
+
+    Ok(())
+}
+
+fn merge_xattrs<Q>(kept: &Path, removed: &[Q]) -> io::Result<()>
+where
+    Q: AsRef<Path>,
+{
+    let mut names = xattr::list(kept)?.collect::<HashSet<_>>();
+    for f in removed {
+        for name in xattr::list(f)? {
+            if names.contains(&name) {
+                continue;
+            }
+
+            if let Some(value) = xattr::get(f, &name)? {
+                xattr::set(kept, &name, &value)?;
+            }
+            names.insert(name);
+        }
+    }
+
+    Ok(())
+}
+
+fn merge_mode<Q>(kept: &Path, removed: &[Q], merge: &ModeMerge) -> io::Result<()>
+where
+    Q: AsRef<Path>,
+{
+    let kept_mode = fs::metadata(kept)?.permissions().mode();
+
+    let mut bits = kept_mode & PERMISSION_BITS;
+    for f in removed {
+        let mode = fs::metadata(f)?.permissions().mode() & PERMISSION_BITS;
+        bits = match merge {
+            ModeMerge::Permissive => bits | mode,
+            ModeMerge::Restrictive => bits & mode,
+        };
+    }
+
+    let mode = (kept_mode & !PERMISSION_BITS) | bits;
+    fs::set_permissions(kept, Permissions::from_mode(mode))
+}
+
+fn merge_times<Q>(kept: &Path, removed: &[Q], options: &MergeOptions) -> io::Result<()>
+where
+    Q: AsRef<Path>,
+{
+    let metadata = fs::metadata(kept)?;
+    let mut mtime = FileTime::from_last_modification_time(&metadata);
+    let mut atime = FileTime::from_last_access_time(&metadata);
+    for f in removed {
+        let metadata = fs::metadata(f)?;
+        if options.mtime {
+            mtime = mtime.min(FileTime::from_last_modification_time(&metadata));
+        }
+        if options.atime {
+            atime = atime.min(FileTime::from_last_access_time(&metadata));
+        }
+    }
+
+    filetime::set_file_times(kept, atime, mtime)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metadata::{self, MergeOptions, ModeMerge};
+    use filetime::{self, FileTime};
+    use std::fs::{self, Permissions};
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn merge_times_and_mode() {
+        let kept = NamedTempFile::new().unwrap();
+        let removed1 = NamedTempFile::new().unwrap();
+        let removed2 = NamedTempFile::new().unwrap();
+
+        filetime::set_file_mtime(kept.path(), FileTime::from_unix_time(2000, 0)).unwrap();
+        filetime::set_file_mtime(removed1.path(), FileTime::from_unix_time(1000, 0)).unwrap();
+        filetime::set_file_mtime(removed2.path(), FileTime::from_unix_time(3000, 0)).unwrap();
+        fs::set_permissions(kept.path(), Permissions::from_mode(0o640)).unwrap();
+        fs::set_permissions(removed1.path(), Permissions::from_mode(0o755)).unwrap();
+        fs::set_permissions(removed2.path(), Permissions::from_mode(0o600)).unwrap();
+
+        let options = MergeOptions {
+            mtime: true,
+            mode: Some(ModeMerge::Permissive),
+            ..MergeOptions::default()
+        };
+        metadata::merge(kept.path(), &[removed1.path(), removed2.path()], &options).unwrap();
+
+        let merged = fs::metadata(kept.path()).unwrap();
+        assert_eq!(
+            FileTime::from_last_modification_time(&merged),
+            FileTime::from_unix_time(1000, 0)
+        );
+        assert_eq!(merged.permissions().mode() & 0o777, 0o755);
+
+        let options = MergeOptions {
+            mode: Some(ModeMerge::Restrictive),
+            ..MergeOptions::default()
+        };
+        metadata::merge(kept.path(), &[removed2.path()], &options).unwrap();
+
+        let merged = fs::metadata(kept.path()).unwrap();
+        assert_eq!(merged.permissions().mode() & 0o777, 0o600);
+    }
+}
@@ -0,0 +1,199 @@
+use crate::find;
+use fastcdc::v2020::StreamCDC;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const MIN_CHUNK_SIZE: u32 = 16_384;
+const AVERAGE_CHUNK_SIZE: u32 = 65_536;
+const MAX_CHUNK_SIZE: u32 = 262_144;
+/// Files holding a chunk beyond which it no longer pairs files up by itself.
+const MAX_CHUNK_HOLDERS: usize = 64;
+
+/// A pair of files sharing some but not necessarily all content.
+#[derive(Debug)]
+pub struct Overlap {
+    pub files: (PathBuf, PathBuf),
+    /// Jaccard similarity of both files' sets of chunks.
+    pub similarity: f64,
+    pub shared_bytes: u64,
+}
+
+/// Find pairs of files whose content defined chunks have a Jaccard similarity
+/// of at least `threshold`, most similar first.
+///
+/// Files are split with FastCDC so content shifted by insertions still yields
+/// the same chunks. Files smaller than a single chunk are ignored, as are
+/// pairs sharing only chunks common to many files. An
+/// [`std::io::Error`] is returned immediately for any IO errors encountered.
+pub fn overlapping_files<P>(files: &[P], threshold: f64) -> io::Result<Vec<Overlap>>
+where
+    P: AsRef<Path>,
+{
+    let mut candidates = vec![];
+    for f in files {
+        if find::get_file_size(f)? >= MIN_CHUNK_SIZE as u64 {
+            candidates.push(f.as_ref().to_path_buf());
+        }
+    }
+
+    let mut ids = HashMap::<Vec<u8>, usize>::new();
+    let mut chunks = vec![];
+    let mut file_chunks = vec![];
+    for (i, f) in candidates.iter().enumerate() {
+        let mut own = vec![];
+        for (hash, length) in get_chunks(f)? {
+            let id = *ids.entry(hash).or_insert_with(|| {
+                chunks.push((length, vec![]));
+                chunks.len() - 1
+            });
+            chunks[id].1.push(i);
+            own.push(id);
+        }
+        file_chunks.push(own);
+    }
+    drop(ids);
+
+    let mut overlaps = vec![];
+    for (i, own) in file_chunks.iter().enumerate() {
+        // Pairs are only found through chunks held by a few files, so chunks
+        // such as runs of zeros don't make counting quadratic in file count.
+        // Chunks held by many files still count towards pairs found.
+        let (common, rare): (Vec<usize>, Vec<usize>) = own
+            .iter()
+            .partition(|&&id| chunks[id].1.len() > MAX_CHUNK_HOLDERS);
+
+        let mut shared = HashMap::<usize, (usize, u64)>::new();
+        for id in rare {
+            let (length, holders) = &chunks[id];
+            for &j in holders.iter().filter(|&&j| j > i) {
+                let pair = shared.entry(j).or_insert((0, 0));
+                pair.0 += 1;
+                pair.1 += length;
+            }
+        }
+        for id in common {
+            let (length, holders) = &chunks[id];
+            for (j, pair) in shared.iter_mut() {
+                // Holders are in file order, being pushed as files are read.
+                if holders.binary_search(j).is_ok() {
+                    pair.0 += 1;
+                    pair.1 += length;
+                }
+            }
+        }
+
+        for (j, (count, shared_bytes)) in shared {
+            let union = own.len() + file_chunks[j].len() - count;
+            let similarity = count as f64 / union as f64;
+            if similarity >= threshold {
+                overlaps.push(Overlap {
+                    files: (candidates[i].clone(), candidates[j].clone()),
+                    similarity,
+                    shared_bytes,
+                });
+            }
+        }
+    }
+
+    overlaps.sort_by(|a, b| {
+        b.similarity
+            .partial_cmp(&a.similarity)
+            .unwrap()
+            .then_with(|| b.shared_bytes.cmp(&a.shared_bytes))
+            .then_with(|| a.files.cmp(&b.files))
+    });
+
+    Ok(overlaps)
+}
+
+/// Split a file into its distinct chunks, as pairs of hash and length.
+fn get_chunks(path: &Path) -> io::Result<HashSet<(Vec<u8>, u64)>> {
+    let chunker = StreamCDC::new(
+        File::open(path)?,
+        MIN_CHUNK_SIZE,
+        AVERAGE_CHUNK_SIZE,
+        MAX_CHUNK_SIZE,
+    );
+
+    let mut chunks = HashSet::new();
+    for chunk in chunker {
+        let chunk = chunk?;
+        let hash = crypto_hash::digest(find::HASH_ALGORITHM, &chunk.data);
+        chunks.insert((hash, chunk.length as u64));
+    }
+
+    Ok(chunks)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::overlap;
+    use rand::{self, Rng};
+    use std::fs;
+
+    #[test]
+    fn find_overlapping_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut rng = rand::thread_rng();
+        let base = (0..1_000_000).map(|_| rng.gen::<u8>()).collect::<Vec<u8>>();
+        let mut edited = base.clone();
+        edited.splice(500_000..500_000, b"Hello, World!".iter().copied());
+        let unrelated = (0..1_000_000).map(|_| rng.gen::<u8>()).collect::<Vec<u8>>();
+
+        let files = vec![
+            dir.path().join("base"),
+            dir.path().join("edited"),
+            dir.path().join("unrelated"),
+            dir.path().join("small"),
+        ];
+        fs::write(&files[0], &base).unwrap();
+        fs::write(&files[1], &edited).unwrap();
+        fs::write(&files[2], &unrelated).unwrap();
+        fs::write(&files[3], b"Hello").unwrap();
+
+        let overlaps = overlap::overlapping_files(&files, 0.5).unwrap();
+
+        assert_eq!(overlaps.len(), 1);
+        assert_eq!(overlaps[0].files, (files[0].clone(), files[1].clone()));
+        assert!(overlaps[0].similarity < 1.0);
+        // The insertion changes at most the two chunks around it.
+        assert!(overlaps[0].shared_bytes >= 1_000_000 - 2 * 262_144);
+    }
+
+    #[test]
+    fn skip_pairs_of_common_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut rng = rand::thread_rng();
+        let mut random = |len| (0..len).map(|_| rng.gen::<u8>()).collect::<Vec<u8>>();
+        let common = random(300_000);
+
+        let mut files = vec![];
+        for i in 0..overlap::MAX_CHUNK_HOLDERS {
+            let file = dir.path().join(i.to_string());
+            fs::write(&file, [common.clone(), random(100_000)].concat()).unwrap();
+            files.push(file);
+        }
+        let base = [common.clone(), random(1_000_000)].concat();
+        let mut edited = base.clone();
+        edited.splice(800_000..800_000, b"Hello, World!".iter().copied());
+        files.push(dir.path().join("base"));
+        files.push(dir.path().join("edited"));
+        fs::write(&files[files.len() - 2], &base).unwrap();
+        fs::write(&files[files.len() - 1], &edited).unwrap();
+
+        let overlaps = overlap::overlapping_files(&files, 0.5).unwrap();
+
+        assert_eq!(overlaps.len(), 1);
+        assert_eq!(
+            overlaps[0].files,
+            (
+                files[files.len() - 2].clone(),
+                files[files.len() - 1].clone()
+            )
+        );
+        // Common chunks still count towards pairs found through others.
+        assert!(overlaps[0].shared_bytes >= 1_300_000 - 2 * 262_144);
+    }
+}
@@ -0,0 +1,289 @@
+use std::cell::{Cell, RefCell};
+use std::io::{self, IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+const TERMINAL_INTERVAL: Duration = Duration::from_millis(200);
+const JSON_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// A status line if stderr is a terminal, otherwise nothing.
+    Auto,
+    Always,
+    Never,
+    /// A JSON record per line, for other programs to follow.
+    Json,
+}
+
+/// Progress of finding duplicates, periodically reported to stderr.
+///
+/// Reporting is throttled, so updates are cheap enough to make per read.
+pub struct Progress {
+    mode: Mode,
+    last_report: Cell<Option<Instant>>,
+    state: RefCell<State>,
+}
+
+struct State {
+    stage: &'static str,
+    walked: u64,
+    candidates: u64,
+    bytes_hashed: u64,
+    stage_start: Instant,
+    /// Time spent in the stage before it was last started.
+    stage_elapsed: Duration,
+    stage_bytes: u64,
+    stage_bytes_total: u64,
+    /// Totals of stages left for another, resumed when started again.
+    left: Vec<Totals>,
+}
+
+/// Totals of a stage over all partitions it was started for.
+struct Totals {
+    stage: &'static str,
+    candidates: u64,
+    elapsed: Duration,
+    bytes: u64,
+    bytes_total: u64,
+}
+
+impl Progress {
+    pub fn new(mode: Mode) -> Progress {
+        let mode = match mode {
+            Mode::Auto if io::stderr().is_terminal() => Mode::Always,
+            Mode::Auto => Mode::Never,
+            mode => mode,
+        };
+
+        Progress {
+            mode,
+            last_report: Cell::new(None),
+            state: RefCell::new(State {
+                stage: "walk",
+                walked: 0,
+                candidates: 0,
+                bytes_hashed: 0,
+                stage_start: Instant::now(),
+                stage_elapsed: Duration::ZERO,
+                stage_bytes: 0,
+                stage_bytes_total: 0,
+                left: vec![],
+            }),
+        }
+    }
+
+    pub fn hidden() -> Progress {
+        Progress::new(Mode::Never)
+    }
+
+    pub fn walked(&self) {
+        self.state.borrow_mut().walked += 1;
+        self.tick(false);
+    }
+
+    /// Start stage `name` with `candidates` files, which will take about
+    /// `bytes` to be read.
+    ///
+    /// A stage started again, as for each spilled partition, adds to the
+    /// totals it had so far.
+    pub fn start_stage(&self, name: &'static str, candidates: usize, bytes: u64) {
+        {
+            let mut state = self.state.borrow_mut();
+            state.leave_stage();
+            let totals = match state.left.iter().position(|t| t.stage == name) {
+                Some(i) => state.left.swap_remove(i),
+                None => Totals {
+                    stage: name,
+                    candidates: 0,
+                    elapsed: Duration::ZERO,
+                    bytes: 0,
+                    bytes_total: 0,
+                },
+            };
+            state.stage = name;
+            state.candidates = totals.candidates + candidates as u64;
+            state.stage_start = Instant::now();
+            state.stage_elapsed = totals.elapsed;
+            state.stage_bytes = totals.bytes;
+            state.stage_bytes_total = totals.bytes_total + bytes;
+        }
+        self.tick(true);
+    }
+
+    pub fn hashed(&self, bytes: u64) {
+        {
+            let mut state = self.state.borrow_mut();
+            state.bytes_hashed += bytes;
+            state.stage_bytes += bytes;
+        }
+        self.tick(false);
+    }
+
+    /// Report the final state, clearing the status line from a terminal.
+    pub fn finish(&self) {
+        self.state.borrow_mut().stage = "done";
+        match self.mode {
+            Mode::Always => eprint!("\r\x1b[K"),
+            Mode::Json => eprintln!("{}", self.state.borrow().json()),
+            Mode::Auto | Mode::Never => {}
+        }
+    }
+
+    fn tick(&self, force: bool) {
+        let interval = match self.mode {
+            Mode::Always => TERMINAL_INTERVAL,
+            Mode::Json => JSON_INTERVAL,
+            Mode::Auto | Mode::Never => return,
+        };
+
+        let now = Instant::now();
+        if let Some(last) = self.last_report.get() {
+            if !force && now.duration_since(last) < interval {
+                return;
+            }
+        }
+        self.last_report.set(Some(now));
+
+        let state = self.state.borrow();
+        let mut stderr = io::stderr();
+        let _ = match self.mode {
+            Mode::Json => writeln!(stderr, "{}", state.json()),
+            _ => write!(stderr, "\r\x1b[K{}", state.line()),
+        };
+    }
+}
+
+impl State {
+    /// Keep the totals of the current stage, unless it's the walk.
+    fn leave_stage(&mut self) {
+        if self.stage == "walk" {
+            return;
+        }
+
+        let totals = Totals {
+            stage: self.stage,
+            candidates: self.candidates,
+            elapsed: self.stage_elapsed + self.stage_start.elapsed(),
+            bytes: self.stage_bytes,
+            bytes_total: self.stage_bytes_total,
+        };
+        self.left.push(totals);
+    }
+
+    fn bytes_per_second(&self) -> f64 {
+        let elapsed = (self.stage_elapsed + self.stage_start.elapsed()).as_secs_f64();
+        if elapsed > 0.0 {
+            self.stage_bytes as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+
+    fn eta(&self) -> Option<Duration> {
+        let rate = self.bytes_per_second();
+        if self.stage_bytes_total == 0 || rate == 0.0 {
+            return None;
+        }
+
+        let left = self.stage_bytes_total.saturating_sub(self.stage_bytes);
+        Some(Duration::from_secs_f64(left as f64 / rate))
+    }
+
+    fn line(&self) -> String {
+        let mut line = format!(
+            "{}: {} files walked, {} candidates, {:.1} MB hashed, {:.1} MB/s",
+            self.stage,
+            self.walked,
+            self.candidates,
+            self.bytes_hashed as f64 / 1e6,
+            self.bytes_per_second() / 1e6
+        );
+        if let Some(eta) = self.eta() {
+            line.push_str(&format!(", ETA {}", format_duration(eta)));
+        }
+
+        line
+    }
+
+    fn json(&self) -> String {
+        let eta = match self.eta() {
+            Some(eta) => eta.as_secs().to_string(),
+            None => "null".to_string(),
+        };
+
+        format!(
+            "{{\"stage\":\"{}\",\"walked\":{},\"candidates\":{},\"bytes_hashed\":{},\
+             \"stage_bytes\":{},\"stage_bytes_total\":{},\"bytes_per_second\":{:.0},\
+             \"eta_seconds\":{}}}",
+            self.stage,
+            self.walked,
+            self.candidates,
+            self.bytes_hashed,
+            self.stage_bytes,
+            self.stage_bytes_total,
+            self.bytes_per_second(),
+            eta
+        )
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m{:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h{:02}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::progress::{self, Mode, Progress};
+    use std::time::Duration;
+
+    #[test]
+    fn report_progress() {
+        let progress = Progress::new(Mode::Never);
+        progress.walked();
+        progress.walked();
+        progress.start_stage("hash", 2, 0);
+        progress.hashed(1_000);
+
+        let state = progress.state.borrow();
+        assert_eq!(state.eta(), None);
+        assert!(state
+            .line()
+            .starts_with("hash: 2 files walked, 2 candidates, 0.0 MB hashed"));
+        assert!(state.json().starts_with(
+            "{\"stage\":\"hash\",\"walked\":2,\"candidates\":2,\"bytes_hashed\":1000,"
+        ));
+        assert!(state.json().ends_with("\"eta_seconds\":null}"));
+
+        assert_eq!(progress::format_duration(Duration::from_secs(59)), "59s");
+        assert_eq!(progress::format_duration(Duration::from_secs(61)), "1m01s");
+        assert_eq!(
+            progress::format_duration(Duration::from_secs(7320)),
+            "2h02m"
+        );
+    }
+
+    #[test]
+    fn accumulate_stages() {
+        let progress = Progress::new(Mode::Never);
+        progress.start_stage("sample", 4, 100);
+        progress.hashed(100);
+        progress.start_stage("hash", 2, 1_000);
+        progress.hashed(1_000);
+        progress.start_stage("sample", 6, 300);
+        progress.start_stage("hash", 3, 2_000);
+        progress.hashed(500);
+
+        let state = progress.state.borrow();
+        assert_eq!(state.candidates, 5);
+        assert_eq!(state.stage_bytes, 1_500);
+        assert_eq!(state.stage_bytes_total, 3_000);
+        let sample = state.left.iter().find(|t| t.stage == "sample").unwrap();
+        assert_eq!(sample.candidates, 10);
+        assert_eq!((sample.bytes, sample.bytes_total), (100, 400));
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::{BinaryHeap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Find directories left empty after removing `removed`, deepest first.
+///
+/// Only directories strictly inside one of `roots` are considered, so roots
+/// themselves are never returned. Paths in `removed` count as gone whether or
+/// not they still exist, which makes this usable as a forecast for dry runs.
+pub fn empty_dirs<P, Q>(removed: &[P], roots: &[Q]) -> io::Result<Vec<PathBuf>>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let mut gone = removed
+        .iter()
+        .map(|p| p.as_ref().to_path_buf())
+        .collect::<HashSet<PathBuf>>();
+
+    let mut pending = BinaryHeap::new();
+    for p in removed {
+        push_parent(&mut pending, p.as_ref(), roots);
+    }
+
+    let mut visited = HashSet::new();
+    let mut empty = vec![];
+    while let Some((_, dir)) = pending.pop() {
+        if !visited.insert(dir.clone()) {
+            continue;
+        }
+
+        if is_empty_dir(&dir, &gone)? {
+            push_parent(&mut pending, &dir, roots);
+            gone.insert(dir.clone());
+            empty.push(dir);
+        }
+    }
+
+    Ok(empty)
+}
+
+/// Queue parent of `path` by depth if it is strictly inside one of `roots`.
+fn push_parent<Q>(pending: &mut BinaryHeap<(usize, PathBuf)>, path: &Path, roots: &[Q])
+where
+    Q: AsRef<Path>,
+{
+    let parent = match path.parent() {
+        Some(parent) => parent,
+        None => return,
+    };
+
+    let is_inside_root = roots.iter().any(|r| {
+        let r = r.as_ref();
+        parent != r && parent.starts_with(r)
+    });
+    if is_inside_root {
+        pending.push((parent.components().count(), parent.to_path_buf()));
+    }
+}
+
+fn is_empty_dir(dir: &Path, gone: &HashSet<PathBuf>) -> io::Result<bool> {
+    for entry in fs::read_dir(dir)? {
+        if !gone.contains(&entry?.path()) {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prune;
+    use std::fs;
+
+    #[test]
+    fn find_empty_dirs() {
+        let root = tempfile::tempdir().unwrap();
+        let a = root.path().join("a");
+        let b = a.join("b");
+        let c = root.path().join("c");
+        fs::create_dir_all(&b).unwrap();
+        fs::create_dir_all(&c).unwrap();
+        fs::write(b.join("x"), b"Hello").unwrap();
+        fs::write(c.join("x"), b"Hello").unwrap();
+        fs::write(c.join("y"), b"World").unwrap();
+
+        let removed = vec![b.join("x"), c.join("x"), root.path().join("z")];
+        let empty = prune::empty_dirs(&removed, &[root.path()]).unwrap();
+
+        assert_eq!(empty, vec![b, a]);
+    }
+}
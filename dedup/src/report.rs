@@ -0,0 +1,112 @@
+use crate::find::Duplicate;
+use std::cmp::Reverse;
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum SortKey {
+    /// Most wasted bytes first.
+    Wasted,
+    /// Most copies first.
+    Copies,
+    /// By first path.
+    Path,
+}
+
+/// Select duplicates with at least `min_copies` copies, and if `touching` is
+/// given with a copy inside it, sorted by `sort` and then hash.
+pub fn select(
+    duplicates: Vec<Duplicate>,
+    sort: &SortKey,
+    min_copies: usize,
+    touching: Option<&Path>,
+) -> Vec<Duplicate> {
+    let mut selected = duplicates
+        .into_iter()
+        .filter(|d| copies(d) >= min_copies)
+        .filter(|d| touching.map_or(true, |dir| paths(d).any(|p| p.starts_with(dir))))
+        .map(|mut d| {
+            d.files.sort();
+            d.archived.sort();
+            d
+        })
+        .collect::<Vec<Duplicate>>();
+
+    // Sorts are stable, so ties stay in order of hash.
+    selected.sort_by(|a, b| a.hash.cmp(&b.hash));
+    match sort {
+        SortKey::Wasted => selected.sort_by_key(|d| Reverse(d.wasted_bytes())),
+        SortKey::Copies => selected.sort_by_key(|d| Reverse(copies(d))),
+        SortKey::Path => selected.sort_by(|a, b| a.files[0].cmp(&b.files[0])),
+    }
+
+    selected
+}
+
+/// Number of copies of a duplicate, including archive members.
+pub fn copies(duplicate: &Duplicate) -> usize {
+    duplicate.files.len() + duplicate.archived.len()
+}
+
+fn paths(duplicate: &Duplicate) -> impl Iterator<Item = &Path> {
+    duplicate
+        .files
+        .iter()
+        .chain(&duplicate.archived)
+        .map(|p| p.as_path())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::find::Duplicate;
+    use crate::report::{self, SortKey};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn select_duplicates() {
+        let duplicate = |hash: &str, size, files: &[&str]| {
+            Duplicate::new(
+                hash.to_string(),
+                size,
+                files.iter().map(PathBuf::from).collect(),
+            )
+        };
+        let duplicates = || {
+            vec![
+                duplicate("a", 100, &["z/1", "a/1"]),
+                duplicate("b", 10, &["b/1", "b/2", "c/1"]),
+                duplicate("c", 1, &["c/2", "c/3"]),
+            ]
+        };
+        let hashes = |selected: Vec<Duplicate>| {
+            selected
+                .into_iter()
+                .map(|d| d.hash)
+                .collect::<Vec<String>>()
+        };
+
+        let selected = report::select(duplicates(), &SortKey::Wasted, 2, None);
+        assert_eq!(selected[0].files[0], Path::new("a/1"));
+        assert_eq!(hashes(selected), vec!["a", "b", "c"]);
+        assert_eq!(
+            hashes(report::select(duplicates(), &SortKey::Copies, 2, None)),
+            vec!["b", "a", "c"]
+        );
+        assert_eq!(
+            hashes(report::select(duplicates(), &SortKey::Path, 2, None)),
+            vec!["a", "b", "c"]
+        );
+        assert_eq!(
+            hashes(report::select(duplicates(), &SortKey::Path, 3, None)),
+            vec!["b"]
+        );
+        assert_eq!(
+            hashes(report::select(
+                duplicates(),
+                &SortKey::Wasted,
+                2,
+                Some(Path::new("c"))
+            )),
+            vec!["b", "c"]
+        );
+    }
+}
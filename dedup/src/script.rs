@@ -0,0 +1,288 @@
+use std::fs::{self, Metadata};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+const SH_HEADER: &str = "\
+#!/bin/sh
+# Generated by dedup. Review before running.
+
+# Succeed only if $1 is still a regular file of exactly $2 bytes.
+same_size() {
+    if [ -f \"$1\" ] && [ \"$(wc -c < \"$1\")\" -eq \"$2\" ]; then
+        return 0
+    fi
+    printf 'Skipping, size of %s changed\\n' \"$1\" >&2
+    return 1
+}
+
+# Succeed only if $1 is still a directory.
+same_dir() {
+    if [ -d \"$1\" ]; then
+        return 0
+    fi
+    printf 'Skipping, %s is no longer a directory\\n' \"$1\" >&2
+    return 1
+}
+";
+const FISH_HEADER: &str = "\
+#!/usr/bin/env fish
+# Generated by dedup. Review before running.
+
+# Succeed only if $argv[1] is still a regular file of exactly $argv[2] bytes.
+function same_size
+    if test -f $argv[1]; and test (wc -c < $argv[1] | string trim) -eq $argv[2]
+        return 0
+    end
+    printf 'Skipping, size of %s changed\\n' $argv[1] >&2
+    return 1
+end
+
+# Succeed only if $argv[1] is still a directory.
+function same_dir
+    if test -d $argv[1]
+        return 0
+    end
+    printf 'Skipping, %s is no longer a directory\\n' $argv[1] >&2
+    return 1
+end
+";
+
+#[derive(Debug, Clone, Copy)]
+pub enum Shell {
+    Sh,
+    Fish,
+}
+
+/// Writer of a shell script that performs the deletions dedup would have
+/// done, for review and execution outside of dedup.
+pub struct Script<W>
+where
+    W: Write,
+{
+    out: W,
+    shell: Shell,
+}
+
+impl<W> Script<W>
+where
+    W: Write,
+{
+    /// Create a script, writing its header and size guard to `out`.
+    pub fn new(mut out: W, shell: Shell) -> io::Result<Script<W>> {
+        match shell {
+            Shell::Sh => out.write_all(SH_HEADER.as_bytes())?,
+            Shell::Fish => out.write_all(FISH_HEADER.as_bytes())?,
+        }
+
+        Ok(Script { out, shell })
+    }
+
+    /// Write commands removing `redundant` files or directories of a
+    /// duplicate group, each guarded by a check of itself and of `kept`.
+    ///
+    /// Files that are only `similar` to `kept` are checked against their own
+    /// size rather than that of `kept`.
+    pub fn remove<P>(
+        &mut self,
+        hash: &str,
+        kept: P,
+        redundant: &[PathBuf],
+        similar: bool,
+    ) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let kept = kept.as_ref();
+        let metadata = fs::metadata(kept)?;
+
+        writeln!(self.out)?;
+        writeln!(self.out, "# {}", hash)?;
+        writeln!(self.out, "# Kept {}", comment_safe(kept))?;
+        for f in redundant {
+            self.guard(kept, &metadata)?;
+            self.and()?;
+            if similar {
+                self.guard(f, &fs::metadata(f)?)?;
+            } else {
+                self.guard(f, &metadata)?;
+            }
+            self.and()?;
+            if metadata.is_dir() {
+                self.out.write_all(b"rm -r -- ")?;
+            } else {
+                self.out.write_all(b"rm -- ")?;
+            }
+            self.quoted(f)?;
+            writeln!(self.out)?;
+        }
+
+        self.out.flush()
+    }
+
+    /// Write commands removing `redundant` files of a duplicate group whose
+    /// kept copies are `archived` members, each guarded by a size check.
+    pub fn remove_archived(
+        &mut self,
+        hash: &str,
+        size: u64,
+        archived: &[PathBuf],
+        redundant: &[PathBuf],
+    ) -> io::Result<()> {
+        writeln!(self.out)?;
+        writeln!(self.out, "# {}", hash)?;
+        for m in archived {
+            writeln!(self.out, "# Kept {}", comment_safe(m))?;
+        }
+        for f in redundant {
+            self.size_guard(f, size)?;
+            self.and()?;
+            self.out.write_all(b"rm -- ")?;
+            self.quoted(f)?;
+            writeln!(self.out)?;
+        }
+
+        self.out.flush()
+    }
+
+    /// Write commands removing directories left empty by earlier removals,
+    /// which `rmdir` refuses to do if they aren't empty after all.
+    pub fn prune(&mut self, empty_dirs: &[PathBuf]) -> io::Result<()> {
+        if empty_dirs.is_empty() {
+            return Ok(());
+        }
+
+        writeln!(self.out)?;
+        writeln!(self.out, "# Empty directories")?;
+        for dir in empty_dirs {
+            self.out.write_all(b"rmdir -- ")?;
+            self.quoted(dir)?;
+            writeln!(self.out)?;
+        }
+
+        self.out.flush()
+    }
+
+    fn guard(&mut self, path: &Path, kept: &Metadata) -> io::Result<()> {
+        if kept.is_dir() {
+            self.out.write_all(b"same_dir ")?;
+            self.quoted(path)
+        } else {
+            self.size_guard(path, kept.len())
+        }
+    }
+
+    fn size_guard(&mut self, path: &Path, size: u64) -> io::Result<()> {
+        self.out.write_all(b"same_size ")?;
+        self.quoted(path)?;
+        write!(self.out, " {}", size)
+    }
+
+    fn and(&mut self) -> io::Result<()> {
+        match self.shell {
+            Shell::Sh => self.out.write_all(b" && "),
+            Shell::Fish => self.out.write_all(b"; and "),
+        }
+    }
+
+    fn quoted(&mut self, path: &Path) -> io::Result<()> {
+        let quoted = match self.shell {
+            Shell::Sh => sh_quote(path.as_os_str().as_bytes()),
+            Shell::Fish => fish_quote(path.as_os_str().as_bytes()),
+        };
+        self.out.write_all(&quoted)
+    }
+}
+
+/// Single quote for sh, where only `'` needs escaping as `'\''`.
+fn sh_quote(bytes: &[u8]) -> Vec<u8> {
+    let mut quoted = vec![b'\''];
+    for &b in bytes {
+        match b {
+            b'\'' => quoted.extend_from_slice(b"'\\''"),
+            _ => quoted.push(b),
+        }
+    }
+    quoted.push(b'\'');
+
+    quoted
+}
+
+/// Single quote for fish, where `'` and `\` need escaping with `\`.
+fn fish_quote(bytes: &[u8]) -> Vec<u8> {
+    let mut quoted = vec![b'\''];
+    for &b in bytes {
+        match b {
+            b'\'' | b'\\' => quoted.extend_from_slice(&[b'\\', b]),
+            _ => quoted.push(b),
+        }
+    }
+    quoted.push(b'\'');
+
+    quoted
+}
+
+/// Display `path` on a single line so it can't escape a comment.
+fn comment_safe(path: &Path) -> String {
+    path.display().to_string().replace(['\n', '\r'], "?")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::script::{self, Script, Shell};
+    use std::io::Write;
+    use std::path::Path;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn quote_paths() {
+        assert_eq!(script::sh_quote(b"it's"), b"'it'\\''s'".to_vec());
+        assert_eq!(
+            script::fish_quote(b"it's a\\b"),
+            b"'it\\'s a\\\\b'".to_vec()
+        );
+        assert_eq!(script::comment_safe(Path::new("a\nb")), "a?b");
+    }
+
+    #[test]
+    fn script_remove() {
+        let mut kept = NamedTempFile::new().unwrap();
+        kept.write_all(b"Hello").unwrap();
+        let redundant = Path::new("/tmp/it's").to_path_buf();
+
+        let mut out = vec![];
+        let mut script = Script::new(&mut out, Shell::Sh).unwrap();
+        script
+            .remove("abc", kept.path(), &[redundant], false)
+            .unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        let kept = kept.path().display();
+        assert!(out.starts_with("#!/bin/sh\n"));
+        assert!(out.contains(&format!("# abc\n# Kept {}\n", kept)));
+        assert!(out.contains(&format!(
+            "same_size '{}' 5 && same_size '/tmp/it'\\''s' 5 && rm -- '/tmp/it'\\''s'\n",
+            kept
+        )));
+    }
+    #[test]
+    fn script_remove_similar() {
+        let mut kept = NamedTempFile::new().unwrap();
+        kept.write_all(b"Hello").unwrap();
+        let mut redundant = NamedTempFile::new().unwrap();
+        redundant.write_all(b"Hi").unwrap();
+
+        let mut out = vec![];
+        let mut script = Script::new(&mut out, Shell::Sh).unwrap();
+        let files = [redundant.path().to_path_buf()];
+        script.remove("abc", kept.path(), &files, true).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains(&format!(
+            "same_size '{}' 5 && same_size '{}' 2 && rm -- '{}'\n",
+            kept.path().display(),
+            redundant.path().display(),
+            redundant.path().display()
+        )));
+    }
+}
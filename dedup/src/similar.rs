@@ -0,0 +1,394 @@
+use crate::find::Duplicate;
+use image::imageops::{self, FilterType};
+use image::{GrayImage, ImageReader};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const IMAGE_EXTENSIONS: [&str; 5] = ["jpg", "jpeg", "png", "webp", "gif"];
+const HASH_SIDE: u32 = 8;
+const DCT_SIDE: u32 = 32;
+
+#[derive(Debug)]
+pub enum ImageHash {
+    Average,
+    Difference,
+    Perceptual,
+}
+
+/// Find groups of visually similar JPEG, PNG, WebP and GIF images.
+///
+/// Images are similar if the Hamming distance between their perceptual
+/// hashes is at most `max_distance`, and every image of a group is similar
+/// to every other, so whichever is kept resembles the rest. The size of a
+/// group is that of its largest image, as sizes differ per file. Files that
+/// aren't images or can't be decoded are skipped. An [`std::io::Error`] is returned
+/// immediately for any other IO errors encountered.
+pub fn similar_images<P>(
+    files: &[P],
+    algorithm: &ImageHash,
+    max_distance: u32,
+) -> io::Result<Vec<Duplicate>>
+where
+    P: AsRef<Path>,
+{
+    let mut images = vec![];
+    for f in files.iter().map(|f| f.as_ref()).filter(|f| is_image(f)) {
+        match get_image_hash(f, algorithm) {
+            Ok(hash) => images.push((f.to_path_buf(), hash)),
+            Err(image::ImageError::IoError(err)) => return Err(err),
+            Err(err) => eprintln!("Skipping {}: {}", f.display(), err),
+        }
+    }
+
+    let hashes = images.iter().map(|(_, hash)| *hash).collect::<Vec<u64>>();
+    let groups = group_hashes(&hashes, max_distance);
+
+    let mut similar = vec![];
+    for g in groups {
+        let hash = format!("{:016x}", images[g[0]].1);
+        let files = g
+            .into_iter()
+            .map(|i| images[i].0.clone())
+            .collect::<Vec<PathBuf>>();
+        let size = files
+            .iter()
+            .map(|f| Ok(fs::metadata(f)?.len()))
+            .collect::<io::Result<Vec<u64>>>()?;
+
+        similar.push(Duplicate::new(hash, size.into_iter().max().unwrap(), files));
+    }
+
+    Ok(similar)
+}
+
+/// Describe resolution and size of an image for reports.
+pub fn describe<P>(path: P) -> io::Result<String>
+where
+    P: AsRef<Path>,
+{
+    let (width, height) = get_resolution(&path)?;
+    let size = fs::metadata(path)?.len();
+
+    Ok(format!("{}x{}, {} bytes", width, height, size))
+}
+
+pub fn get_resolution<P>(path: P) -> io::Result<(u32, u32)>
+where
+    P: AsRef<Path>,
+{
+    image::image_dimensions(path).map_err(|err| match err {
+        image::ImageError::IoError(err) => err,
+        err => io::Error::new(io::ErrorKind::InvalidData, err),
+    })
+}
+
+fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| IMAGE_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+fn get_image_hash(path: &Path, algorithm: &ImageHash) -> image::ImageResult<u64> {
+    let image = ImageReader::open(path)?.with_guessed_format()?.decode()?;
+    let gray = image.to_luma8();
+
+    Ok(match algorithm {
+        ImageHash::Average => average_hash(&gray),
+        ImageHash::Difference => difference_hash(&gray),
+        ImageHash::Perceptual => perceptual_hash(&gray),
+    })
+}
+
+/// Set a bit for each pixel of an 8x8 thumbnail brighter than its mean.
+fn average_hash(image: &GrayImage) -> u64 {
+    let small = imageops::resize(image, HASH_SIDE, HASH_SIDE, FilterType::Triangle);
+    let pixels = small.pixels().map(|p| p[0] as f64).collect::<Vec<f64>>();
+
+    bits_above(&pixels, mean(&pixels))
+}
+
+/// Set a bit for each pixel of a 9x8 thumbnail brighter than its right
+/// neighbor.
+fn difference_hash(image: &GrayImage) -> u64 {
+    let small = imageops::resize(image, HASH_SIDE + 1, HASH_SIDE, FilterType::Triangle);
+
+    let mut hash = 0;
+    for y in 0..HASH_SIDE {
+        for x in 0..HASH_SIDE {
+            let bit = small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | bit as u64;
+        }
+    }
+
+    hash
+}
+
+/// Set a bit for each of the lowest 8x8 frequencies of a 32x32 thumbnail's
+/// DCT above their median, which survives rescaling and recompression.
+fn perceptual_hash(image: &GrayImage) -> u64 {
+    let small = imageops::resize(image, DCT_SIDE, DCT_SIDE, FilterType::Triangle);
+    let n = DCT_SIDE as usize;
+    let pixels = small.pixels().map(|p| p[0] as f64).collect::<Vec<f64>>();
+
+    // Separable 2D DCT-II, only computing the kept low frequencies.
+    let k = HASH_SIDE as usize;
+    let cos = (0..k)
+        .map(|u| {
+            (0..n)
+                .map(|x| ((2 * x + 1) as f64 * u as f64 * PI / (2 * n) as f64).cos())
+                .collect::<Vec<f64>>()
+        })
+        .collect::<Vec<Vec<f64>>>();
+
+    let mut rows = vec![0.0; n * k];
+    for y in 0..n {
+        for u in 0..k {
+            rows[y * k + u] = (0..n).map(|x| pixels[y * n + x] * cos[u][x]).sum();
+        }
+    }
+
+    let mut coefficients = vec![0.0; k * k];
+    for v in 0..k {
+        for u in 0..k {
+            coefficients[v * k + u] = (0..n).map(|y| rows[y * k + u] * cos[v][y]).sum();
+        }
+    }
+
+    // The DC coefficient is just average brightness, so leave it out.
+    let mut ac = coefficients[1..].to_vec();
+    ac.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    bits_above(&coefficients, ac[ac.len() / 2])
+}
+
+fn bits_above(values: &[f64], threshold: f64) -> u64 {
+    values
+        .iter()
+        .fold(0, |hash, &v| (hash << 1) | (v > threshold) as u64)
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// A BK-tree of hashes for finding those within a Hamming distance without
+/// comparing every pair.
+struct BkTree {
+    nodes: Vec<BkNode>,
+}
+
+struct BkNode {
+    hash: u64,
+    values: Vec<usize>,
+    children: HashMap<u32, usize>,
+}
+
+impl BkTree {
+    fn new() -> BkTree {
+        BkTree { nodes: vec![] }
+    }
+
+    fn insert(&mut self, hash: u64, value: usize) {
+        let new_node = BkNode {
+            hash,
+            values: vec![value],
+            children: HashMap::new(),
+        };
+        if self.nodes.is_empty() {
+            self.nodes.push(new_node);
+            return;
+        }
+
+        let mut current = 0;
+        loop {
+            let distance = hamming_distance(self.nodes[current].hash, hash);
+            if distance == 0 {
+                self.nodes[current].values.push(value);
+                return;
+            }
+
+            match self.nodes[current].children.get(&distance) {
+                Some(&child) => current = child,
+                None => {
+                    let index = self.nodes.len();
+                    self.nodes.push(new_node);
+                    self.nodes[current].children.insert(distance, index);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Find values of all hashes within `max_distance` of `hash`.
+    fn find(&self, hash: u64, max_distance: u32) -> Vec<usize> {
+        let mut found = vec![];
+        if self.nodes.is_empty() {
+            return found;
+        }
+
+        let mut pending = vec![0];
+        while let Some(current) = pending.pop() {
+            let node = &self.nodes[current];
+            let distance = hamming_distance(node.hash, hash);
+            if distance <= max_distance {
+                found.extend(node.values.iter().copied());
+            }
+
+            // By the triangle inequality, only children whose edge is within
+            // max_distance of distance can hold matches.
+            for (&edge, &child) in &node.children {
+                if edge + max_distance >= distance && edge <= distance + max_distance {
+                    pending.push(child);
+                }
+            }
+        }
+
+        found
+    }
+}
+
+/// Group indices of `hashes` so that all hashes of a group are within
+/// `max_distance` of each other, leaving out those similar to no other.
+///
+/// Hashes with the most similar ones go first, each gathering the closest of
+/// those not grouped yet that are similar to all of its group so far.
+fn group_hashes(hashes: &[u64], max_distance: u32) -> Vec<Vec<usize>> {
+    let mut tree = BkTree::new();
+    for (i, hash) in hashes.iter().enumerate() {
+        tree.insert(*hash, i);
+    }
+    let neighbours = hashes
+        .iter()
+        .map(|hash| tree.find(*hash, max_distance))
+        .collect::<Vec<Vec<usize>>>();
+
+    let mut order = (0..hashes.len()).collect::<Vec<usize>>();
+    order.sort_by_key(|&i| (Reverse(neighbours[i].len()), i));
+
+    let mut grouped = vec![false; hashes.len()];
+    let mut groups = vec![];
+    for i in order {
+        if grouped[i] {
+            continue;
+        }
+
+        let mut candidates = neighbours[i]
+            .iter()
+            .copied()
+            .filter(|&j| j != i && !grouped[j])
+            .collect::<Vec<usize>>();
+        candidates.sort_by_key(|&j| (hamming_distance(hashes[i], hashes[j]), j));
+
+        let mut group = vec![i];
+        for j in candidates {
+            if group
+                .iter()
+                .all(|&k| hamming_distance(hashes[j], hashes[k]) <= max_distance)
+            {
+                group.push(j);
+            }
+        }
+
+        // Left ungrouped, so it may still join a later group.
+        if group.len() > 1 {
+            group.iter().for_each(|&k| grouped[k] = true);
+            groups.push(group);
+        }
+    }
+
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::similar::{self, BkTree, ImageHash};
+    use image::{imageops, GrayImage, Luma};
+    use std::collections::HashSet;
+    use std::iter::FromIterator;
+
+    #[test]
+    fn bk_tree_find() {
+        let mut tree = BkTree::new();
+        tree.insert(0b0000, 0);
+        tree.insert(0b0001, 1);
+        tree.insert(0b0011, 2);
+        tree.insert(0b1111, 3);
+        tree.insert(0b0001, 4);
+
+        let found = HashSet::<usize>::from_iter(tree.find(0b0000, 1));
+        assert_eq!(found, HashSet::from_iter(vec![0, 1, 4]));
+        assert_eq!(tree.find(0b1110, 0), vec![]);
+    }
+
+    #[test]
+    fn group_without_chaining() {
+        // Each hash is similar to the next, but the first isn't to the last.
+        let hashes = [0b0000_0000, 0b0000_1111, 0b1111_1111];
+        let groups = similar::group_hashes(&hashes, 4);
+
+        assert_eq!(groups, vec![vec![1, 0]]);
+        for g in &groups {
+            for &a in g {
+                for &b in g {
+                    assert!(similar::hamming_distance(hashes[a], hashes[b]) <= 4);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn find_similar_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let waves = GrayImage::from_fn(128, 128, |x, y| {
+            let wave = (x as f64 / 9.0).sin() * (y as f64 / 13.0).cos();
+            let square = if x > 70 && y > 20 && y < 60 {
+                60.0
+            } else {
+                0.0
+            };
+            Luma([(wave * 60.0 + 120.0 + square) as u8])
+        });
+        let pattern =
+            GrayImage::from_fn(128, 128, |x, y| Luma([((x / 16 + y / 32) % 2 * 255) as u8]));
+
+        let original = dir.path().join("original.png");
+        let resized = dir.path().join("resized.jpg");
+        let different = dir.path().join("different.png");
+        let not_image = dir.path().join("notes.txt");
+        waves.save(&original).unwrap();
+        imageops::resize(&waves, 64, 64, imageops::FilterType::Triangle)
+            .save(&resized)
+            .unwrap();
+        pattern.save(&different).unwrap();
+        std::fs::write(&not_image, b"Hello").unwrap();
+
+        for algorithm in &[
+            ImageHash::Average,
+            ImageHash::Difference,
+            ImageHash::Perceptual,
+        ] {
+            let files = vec![&original, &resized, &different, &not_image];
+            let similar = similar::similar_images(&files, algorithm, 4).unwrap();
+
+            assert_eq!(similar.len(), 1);
+            assert_eq!(
+                HashSet::<_>::from_iter(similar[0].files.iter()),
+                HashSet::from_iter(vec![&original, &resized])
+            );
+        }
+
+        assert_eq!(
+            similar::describe(&resized).unwrap().split(',').next(),
+            Some("64x64")
+        );
+    }
+}
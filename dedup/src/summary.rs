@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const UNITS: [&str; 5] = ["kB", "MB", "GB", "TB", "PB"];
+
+/// Tally of a run, forecast by dry runs and scripts or counted as applied.
+#[derive(Debug, Default)]
+pub struct Summary {
+    groups: usize,
+    removed: usize,
+    bytes: u64,
+    errors: usize,
+    dir_bytes: HashMap<PathBuf, u64>,
+}
+
+impl Summary {
+    pub fn group(&mut self) {
+        self.groups += 1;
+    }
+
+    /// Count `path` of `size` bytes as removed, towards its parent directory.
+    pub fn removed(&mut self, path: &Path, size: u64) {
+        self.removed += 1;
+        self.bytes += size;
+
+        let dir = path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+        *self.dir_bytes.entry(dir).or_default() += size;
+    }
+
+    pub fn error(&mut self) {
+        self.errors += 1;
+    }
+
+    /// Whether nothing was tallied, neither groups nor errors.
+    pub fn is_empty(&self) -> bool {
+        self.groups == 0 && self.errors == 0
+    }
+
+    /// Add the tally of `other` to this one.
+    pub fn merge(&mut self, other: Summary) {
+        self.groups += other.groups;
+        self.removed += other.removed;
+        self.bytes += other.bytes;
+        self.errors += other.errors;
+        for (dir, bytes) in other.dir_bytes {
+            *self.dir_bytes.entry(dir).or_default() += bytes;
+        }
+    }
+
+    /// Write totals and the `top` directories holding the most redundant
+    /// bytes, worded as a forecast if `forecast`.
+    pub fn write<W>(&self, out: &mut W, top: usize, forecast: bool) -> io::Result<()>
+    where
+        W: Write,
+    {
+        let (removed, reclaimed) = if forecast {
+            ("Files to remove", "Bytes to reclaim")
+        } else {
+            ("Files removed", "Bytes reclaimed")
+        };
+
+        writeln!(out, "Summary")?;
+        writeln!(out, "\tDuplicate groups: {}", self.groups)?;
+        writeln!(out, "\t{}: {}", removed, self.removed)?;
+        writeln!(
+            out,
+            "\t{}: {} ({})",
+            reclaimed,
+            self.bytes,
+            format_bytes(self.bytes)
+        )?;
+        writeln!(out, "\tErrors: {}", self.errors)?;
+
+        if top == 0 || self.dir_bytes.is_empty() {
+            return Ok(());
+        }
+
+        let mut dirs = self.dir_bytes.iter().collect::<Vec<(&PathBuf, &u64)>>();
+        dirs.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        writeln!(out, "Directories with most redundant bytes")?;
+        for (dir, bytes) in dirs.into_iter().take(top) {
+            writeln!(out, "\t{:>10}\t{}", format_bytes(*bytes), dir.display())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Format bytes in decimal units, as in `1.5 MB`.
+fn format_bytes(bytes: u64) -> String {
+    if bytes < 1_000 {
+        return format!("{} B", bytes);
+    }
+
+    let mut value = bytes as f64 / 1_000.0;
+    let mut unit = 0;
+    while value >= 1_000.0 && unit < UNITS.len() - 1 {
+        value /= 1_000.0;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::summary::{self, Summary};
+    use std::path::Path;
+
+    #[test]
+    fn write_summary() {
+        let mut summary = Summary::default();
+        summary.group();
+        summary.removed(Path::new("a/x"), 1_500);
+        summary.removed(Path::new("b/x"), 1_500_000);
+        summary.removed(Path::new("a/y"), 1_500);
+        summary.error();
+
+        let mut out = vec![];
+        summary.write(&mut out, 1, true).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Summary\n\
+             \tDuplicate groups: 1\n\
+             \tFiles to remove: 3\n\
+             \tBytes to reclaim: 1503000 (1.5 MB)\n\
+             \tErrors: 1\n\
+             Directories with most redundant bytes\n\
+             \t    1.5 MB\tb\n"
+        );
+
+        assert_eq!(summary::format_bytes(999), "999 B");
+        assert_eq!(summary::format_bytes(2_500_000_000), "2.5 GB");
+    }
+}
@@ -0,0 +1,244 @@
+use crate::filesystem;
+use crate::find::{self, Duplicate, Options};
+use crate::keep::{self, KeepCriteria};
+use crypto_hash::{self, Hasher};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+#[derive(Default)]
+struct Node {
+    size: u64,
+    files: Vec<(OsString, String)>,
+    dirs: Vec<PathBuf>,
+}
+
+/// Find all duplicate directory trees under `directories`, largest first.
+///
+/// A directory's hash is derived from the names and hashes of its descendant
+/// files and directories, empty ones included. Directories nested in one that
+/// an already reported duplicate would remove, as chosen by `keep_criteria`,
+/// are omitted. An [`std::io::Error`] is returned immediately for any IO
+/// errors encountered.
+pub fn duplicate_dirs<P>(
+    directories: &[P],
+    keep_criteria: &KeepCriteria,
+) -> io::Result<Vec<Duplicate>>
+where
+    P: AsRef<Path>,
+{
+    let roots = directories
+        .iter()
+        .map(|d| d.as_ref().to_path_buf())
+        .collect::<HashSet<PathBuf>>();
+
+    let mut files = vec![];
+    let mut dirs = vec![];
+    for d in &roots {
+        files.extend(filesystem::walk_files(d)?);
+        dirs.extend(filesystem::walk_dirs(d)?);
+    }
+
+    let nodes = build_nodes(&files, &dirs, &roots)?;
+    let hash_groups = group_by_hash(nodes);
+
+    omit_nested(hash_groups, keep_criteria)
+}
+
+/// Build the nodes of every directory.
+///
+/// Files are identified by content hash when they have a duplicate, and
+/// otherwise by their unique path so their parents never match.
+fn build_nodes(
+    files: &[PathBuf],
+    dirs: &[PathBuf],
+    roots: &HashSet<PathBuf>,
+) -> io::Result<HashMap<PathBuf, Node>> {
+    let file_hashes = find::duplicate_files(files.iter().collect(), &Options::default(), &[])?
+        .duplicates
+        .into_iter()
+        .flat_map(|d| {
+            let hash = d.hash;
+            d.files.into_iter().map(move |f| (f, hash.clone()))
+        })
+        .collect::<HashMap<PathBuf, String>>();
+    let empty_hash = crypto_hash::hex_digest(find::HASH_ALGORITHM, &[]);
+
+    let mut nodes = HashMap::<PathBuf, Node>::new();
+    for d in dirs {
+        nodes.entry(d.clone()).or_default();
+    }
+    for f in files {
+        let size = find::get_file_size(f)?;
+        let hash = match file_hashes.get(f) {
+            Some(hash) => hash.clone(),
+            None if size == 0 => empty_hash.clone(),
+            None => format!("unique {}", f.display()),
+        };
+
+        let (parent, name) = split_path(f);
+        let node = nodes.entry(parent.to_path_buf()).or_default();
+        node.size += size;
+        node.files.push((name, hash));
+    }
+
+    let mut pending = nodes.keys().cloned().collect::<Vec<PathBuf>>();
+    while let Some(dir) = pending.pop() {
+        if roots.contains(&dir) {
+            continue;
+        }
+
+        let (parent, _) = split_path(&dir);
+        if !nodes.contains_key(parent) {
+            pending.push(parent.to_path_buf());
+        }
+        nodes
+            .entry(parent.to_path_buf())
+            .or_default()
+            .dirs
+            .push(dir);
+    }
+
+    Ok(nodes)
+}
+
+/// Group directories by Merkle hash, omitting singletons and empty trees.
+fn group_by_hash(mut nodes: HashMap<PathBuf, Node>) -> Vec<Duplicate> {
+    let mut dirs = nodes.keys().cloned().collect::<Vec<PathBuf>>();
+    dirs.sort_by_key(|d| Reverse(d.components().count()));
+
+    let mut hashes = HashMap::<PathBuf, (String, u64)>::new();
+    for dir in dirs {
+        let node = nodes.remove(&dir).unwrap();
+
+        let mut entries = node
+            .files
+            .into_iter()
+            .map(|(name, hash)| (name, 'f', hash))
+            .collect::<Vec<(OsString, char, String)>>();
+        let mut size = node.size;
+        for d in node.dirs {
+            let (hash, dir_size) = hashes[&d].clone();
+            size += dir_size;
+            entries.push((split_path(&d).1, 'd', hash));
+        }
+        entries.sort();
+
+        let mut hasher = Hasher::new(find::HASH_ALGORITHM);
+        for (name, kind, hash) in entries {
+            // Names can't contain NUL, which makes the encoding unambiguous.
+            hasher.write_all(&[kind as u8, 0]).unwrap();
+            hasher.write_all(name.as_bytes()).unwrap();
+            hasher.write_all(&[0]).unwrap();
+            hasher.write_all(hash.as_bytes()).unwrap();
+        }
+
+        hashes.insert(dir, (hex::encode(hasher.finish()), size));
+    }
+
+    let mut groups = HashMap::<(String, u64), Vec<PathBuf>>::new();
+    for (dir, key) in hashes {
+        groups.entry(key).or_default().push(dir);
+    }
+
+    groups
+        .into_iter()
+        .filter(|((_, size), g)| *size != 0 && g.len() != 1)
+        .map(|((hash, size), g)| Duplicate::new(hash, size, g))
+        .collect()
+}
+
+/// Sort duplicates largest and then shallowest first, dropping directories
+/// inside ones that earlier duplicates remove, since those are removed along
+/// with their ancestor.
+fn omit_nested(
+    mut duplicates: Vec<Duplicate>,
+    keep_criteria: &KeepCriteria,
+) -> io::Result<Vec<Duplicate>> {
+    duplicates.sort_by_cached_key(|d| {
+        let depth = d.files.iter().map(|f| f.components().count()).min();
+        (Reverse(d.size), depth)
+    });
+
+    let mut removed = HashSet::<PathBuf>::new();
+    let mut outermost = vec![];
+    for mut d in duplicates {
+        d.files
+            .retain(|f| !f.ancestors().skip(1).any(|a| removed.contains(a)));
+        if d.files.len() < 2 {
+            continue;
+        }
+
+        d.files.sort();
+        let kept = keep::by_criteria(&d.files, keep_criteria)?;
+        removed.extend(d.files.iter().filter(|f| **f != kept).cloned());
+        outermost.push(d);
+    }
+
+    Ok(outermost)
+}
+
+fn split_path(path: &Path) -> (&Path, OsString) {
+    let parent = path.parent().unwrap_or_else(|| Path::new(""));
+    let name = path.file_name().unwrap_or_default().to_os_string();
+
+    (parent, name)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::keep::KeepCriteria;
+    use crate::tree;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn find_duplicate_dirs() {
+        let root = tempfile::tempdir().unwrap();
+        let write = |path: &str, content: &[u8]| {
+            let path = root.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        };
+
+        write("a/x", b"Hello");
+        write("a/sub/y", b"World");
+        write("b/x", b"Hello");
+        write("b/sub/y", b"World");
+        write("c/sub/y", b"World");
+        write("d/x", b"Hello");
+        write("d/z", b"Unique");
+
+        let duplicates = tree::duplicate_dirs(&[root.path()], &KeepCriteria::Newest).unwrap();
+        let paths = duplicates
+            .iter()
+            .map(|d| {
+                d.files
+                    .iter()
+                    .map(|f| f.strip_prefix(root.path()).unwrap().to_path_buf())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        // Of a/sub, b/sub and c/sub, only the one removed along with a or b
+        // is omitted.
+        assert_eq!(paths.len(), 2);
+        assert_eq!(
+            paths[0],
+            vec![Path::new("a").to_path_buf(), Path::new("b").to_path_buf()]
+        );
+        assert_eq!(duplicates[0].size, 10);
+        assert_eq!(paths[1].len(), 2);
+        assert!(paths[1].contains(&Path::new("c/sub").to_path_buf()));
+
+        // An empty directory makes an otherwise identical tree differ.
+        fs::create_dir(root.path().join("a/empty")).unwrap();
+        let duplicates = tree::duplicate_dirs(&[root.path()], &KeepCriteria::Newest).unwrap();
+        assert!(duplicates
+            .iter()
+            .all(|d| !d.files.contains(&root.path().join("a"))));
+    }
+}
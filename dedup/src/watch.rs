@@ -0,0 +1,423 @@
+use crate::find::{self, Duplicate};
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+const EVENT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Files indexed by size, with content hashes computed once another file of
+/// the same size shows up.
+#[derive(Debug, Default)]
+pub struct Index {
+    sizes: HashMap<PathBuf, u64>,
+    modified: HashMap<PathBuf, SystemTime>,
+    by_size: HashMap<u64, HashMap<PathBuf, Option<String>>>,
+}
+
+impl Index {
+    pub fn contains(&self, path: &Path) -> bool {
+        self.sizes.contains_key(path)
+    }
+
+    /// Index a file, returning it along with indexed files of identical
+    /// content if there are any. Zero byte files are ignored, and files that
+    /// turn out to be gone are dropped from the index.
+    pub fn add(&mut self, path: &Path) -> io::Result<Option<Duplicate>> {
+        self.remove(path);
+        let metadata = fs::metadata(path)?;
+        let size = metadata.len();
+        if size == 0 {
+            return Ok(None);
+        }
+
+        let group = self.by_size.entry(size).or_default();
+        let hash = if group.is_empty() {
+            None
+        } else {
+            Some(find::hash_file(path)?)
+        };
+
+        let mut files = vec![path.to_path_buf()];
+        let mut gone = vec![];
+        if let Some(hash) = &hash {
+            for (other, other_hash) in group.iter_mut() {
+                if !other.is_file() {
+                    gone.push(other.clone());
+                    continue;
+                }
+                if other_hash.is_none() {
+                    *other_hash = Some(find::hash_file(other)?);
+                }
+                if other_hash.as_ref() == Some(hash) {
+                    files.push(other.clone());
+                }
+            }
+        }
+        group.insert(path.to_path_buf(), hash.clone());
+        self.sizes.insert(path.to_path_buf(), size);
+        self.modified
+            .insert(path.to_path_buf(), metadata.modified()?);
+        for g in gone {
+            self.remove(&g);
+        }
+
+        match (hash, files.len()) {
+            (Some(hash), n) if n > 1 => Ok(Some(Duplicate::new(hash, size, files))),
+            _ => Ok(None),
+        }
+    }
+
+    /// Size and modification time of each indexed file, as when indexed.
+    pub fn stamps(&self) -> HashMap<PathBuf, (u64, SystemTime)> {
+        self.sizes
+            .iter()
+            .map(|(path, size)| (path.clone(), (*size, self.modified[path])))
+            .collect()
+    }
+
+    pub fn remove(&mut self, path: &Path) {
+        self.modified.remove(path);
+        if let Some(size) = self.sizes.remove(path) {
+            let group = self.by_size.get_mut(&size).unwrap();
+            group.remove(path);
+            if group.is_empty() {
+                self.by_size.remove(&size);
+            }
+        }
+    }
+
+    /// Remove all files under `dir`.
+    pub fn remove_tree(&mut self, dir: &Path) {
+        let under = self
+            .sizes
+            .keys()
+            .filter(|p| p.starts_with(dir))
+            .cloned()
+            .collect::<Vec<PathBuf>>();
+        for p in under {
+            self.remove(&p);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.sizes.clear();
+        self.modified.clear();
+        self.by_size.clear();
+    }
+}
+
+/// Watcher of directory trees that indexes their files and reports files
+/// closed after writing, or moved in, that duplicate an indexed file.
+pub struct Watcher {
+    inotify: Inotify,
+    roots: Vec<PathBuf>,
+    dirs: HashMap<WatchDescriptor, PathBuf>,
+    index: Index,
+    buffer: Vec<u8>,
+}
+
+impl Watcher {
+    /// Recursively watch and index `roots`, without reporting duplicates
+    /// already among them.
+    pub fn new<P>(roots: &[P]) -> io::Result<Watcher>
+    where
+        P: AsRef<Path>,
+    {
+        let mut watcher = Watcher {
+            inotify: Inotify::init()?,
+            roots: roots.iter().map(|r| r.as_ref().to_path_buf()).collect(),
+            dirs: HashMap::new(),
+            index: Index::default(),
+            buffer: vec![0; EVENT_BUFFER_SIZE],
+        };
+        watcher.rescan(false)?;
+
+        Ok(watcher)
+    }
+
+    /// Handle pending events without blocking, returning the duplicates
+    /// they formed.
+    #[cfg(test)]
+    pub fn poll(&mut self) -> io::Result<Vec<Duplicate>> {
+        self.read(false)
+    }
+
+    /// Wait for events and handle them, returning the duplicates they formed.
+    pub fn wait(&mut self) -> io::Result<Vec<Duplicate>> {
+        self.read(true)
+    }
+
+    fn read(&mut self, blocking: bool) -> io::Result<Vec<Duplicate>> {
+        let events = if blocking {
+            self.inotify.read_events_blocking(&mut self.buffer)
+        } else {
+            self.inotify.read_events(&mut self.buffer)
+        };
+        let events = match events {
+            Ok(events) => events
+                .map(|e| (e.wd, e.mask, e.name.map(|n| n.to_os_string())))
+                .collect::<Vec<(WatchDescriptor, EventMask, Option<OsString>)>>(),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(vec![]),
+            Err(err) => return Err(err),
+        };
+
+        let mut duplicates = vec![];
+        for (wd, mask, name) in events {
+            if mask.contains(EventMask::Q_OVERFLOW) {
+                // Events were lost, so start over from what's on disk, which
+                // may hold duplicates formed by any of them.
+                duplicates.extend(self.rescan(true)?);
+                continue;
+            }
+            if mask.contains(EventMask::IGNORED) {
+                self.dirs.remove(&wd);
+                continue;
+            }
+
+            let path = match (self.dirs.get(&wd), name) {
+                (Some(dir), Some(name)) => dir.join(name),
+                _ => continue,
+            };
+            let added = mask.intersects(EventMask::CLOSE_WRITE | EventMask::MOVED_TO);
+            let removed = mask.intersects(EventMask::DELETE | EventMask::MOVED_FROM);
+
+            if mask.contains(EventMask::ISDIR) {
+                if mask.contains(EventMask::CREATE) {
+                    // Files in it may still be written, so wait for them to
+                    // be closed.
+                    self.add_dir(&path, false)?;
+                } else if mask.contains(EventMask::MOVED_TO) {
+                    duplicates.extend(self.add_dir(&path, true)?);
+                } else if removed {
+                    self.remove_dir(&path);
+                }
+            } else if added {
+                match self.index.add(&path) {
+                    Ok(duplicate) => duplicates.extend(duplicate),
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                    Err(err) => return Err(err),
+                }
+            } else if removed {
+                self.index.remove(&path);
+            }
+        }
+
+        Ok(duplicates)
+    }
+
+    /// Forget everything and watch and index all roots again. If `report`,
+    /// returns duplicates of files that are new or changed since they were
+    /// last indexed, leaving those among unchanged files alone as at start.
+    fn rescan(&mut self, report: bool) -> io::Result<Vec<Duplicate>> {
+        let known = self.index.stamps();
+        for (wd, _) in self.dirs.drain() {
+            let _ = self.inotify.watches().remove(wd);
+        }
+        self.index.clear();
+
+        for root in self.roots.clone() {
+            self.add_dir(&root, true)?;
+        }
+        if !report {
+            return Ok(vec![]);
+        }
+
+        let mut changed = self
+            .index
+            .stamps()
+            .into_iter()
+            .filter(|(path, stamp)| known.get(path) != Some(stamp))
+            .map(|(path, _)| path)
+            .collect::<Vec<PathBuf>>();
+        changed.sort();
+
+        let mut duplicates = vec![];
+        let mut reported = HashSet::new();
+        for path in changed {
+            if reported.contains(&path) {
+                continue;
+            }
+            // Indexing it again returns the indexed files it duplicates.
+            match self.index.add(&path) {
+                Ok(Some(duplicate)) => {
+                    reported.extend(duplicate.files.iter().cloned());
+                    duplicates.push(duplicate);
+                }
+                Ok(None) => {}
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(duplicates)
+    }
+
+    /// Watch a directory tree, and if `index_files` index its files not
+    /// indexed yet, returning duplicates among them.
+    ///
+    /// Files of a tree that was just created are left to be indexed once
+    /// closed after writing, so files already closed by the time it's
+    /// watched are missed until a rescan.
+    fn add_dir(&mut self, dir: &Path, index_files: bool) -> io::Result<Vec<Duplicate>> {
+        let mask = WatchMask::CLOSE_WRITE
+            | WatchMask::CREATE
+            | WatchMask::DELETE
+            | WatchMask::MOVED_FROM
+            | WatchMask::MOVED_TO
+            | WatchMask::ONLYDIR;
+
+        let mut duplicates = vec![];
+        let mut pending = vec![dir.to_path_buf()];
+        while let Some(dir) = pending.pop() {
+            let entries = match self.inotify.watches().add(&dir, mask) {
+                Ok(wd) => {
+                    self.dirs.insert(wd, dir.clone());
+                    fs::read_dir(&dir)
+                }
+                Err(err) => Err(err),
+            };
+            // Directories may be gone again by the time they're walked.
+            let entries = match entries {
+                Ok(entries) => entries,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+
+            for e in entries {
+                let path = e?.path();
+                if path.is_dir() {
+                    pending.push(path);
+                } else if index_files && path.is_file() && !self.index.contains(&path) {
+                    match self.index.add(&path) {
+                        Ok(duplicate) => duplicates.extend(duplicate),
+                        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                        Err(err) => return Err(err),
+                    }
+                }
+            }
+        }
+
+        Ok(duplicates)
+    }
+
+    /// Stop watching a directory tree moved or deleted away, and forget its
+    /// files.
+    fn remove_dir(&mut self, dir: &Path) {
+        let wds = self
+            .dirs
+            .iter()
+            .filter(|(_, d)| d.starts_with(dir))
+            .map(|(wd, _)| wd.clone())
+            .collect::<Vec<WatchDescriptor>>();
+        for wd in wds {
+            self.dirs.remove(&wd);
+            let _ = self.inotify.watches().remove(wd);
+        }
+        self.index.remove_tree(dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::watch::{Index, Watcher};
+    use std::collections::HashSet;
+    use std::fs;
+    use std::iter::FromIterator;
+
+    #[test]
+    fn index_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a");
+        let b = dir.path().join("b");
+        let c = dir.path().join("c");
+        fs::write(&a, b"Hello").unwrap();
+        fs::write(&b, b"World").unwrap();
+        fs::write(&c, b"Hello").unwrap();
+
+        let mut index = Index::default();
+        assert!(index.add(&a).unwrap().is_none());
+        assert!(index.add(&b).unwrap().is_none());
+        let duplicate = index.add(&c).unwrap().unwrap();
+        assert_eq!(
+            HashSet::<_>::from_iter(duplicate.files),
+            HashSet::from_iter(vec![a.clone(), c.clone()])
+        );
+
+        fs::remove_file(&a).unwrap();
+        assert!(index.add(&c).unwrap().is_none());
+        assert!(!index.contains(&a));
+    }
+
+    #[test]
+    fn watch_new_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = dir.path().join("original");
+        fs::write(&original, b"Hello").unwrap();
+        fs::write(dir.path().join("existing"), b"Hello").unwrap();
+
+        let mut watcher = Watcher::new(&[dir.path()]).unwrap();
+        assert!(watcher.poll().unwrap().is_empty());
+
+        let copy = dir.path().join("copy");
+        fs::write(&copy, b"Hello").unwrap();
+        fs::write(dir.path().join("unique"), b"World").unwrap();
+        let duplicates = watcher.poll().unwrap();
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].files.len(), 3);
+        assert!(duplicates[0].files.contains(&copy));
+
+        let nested = dir.path().join("new").join("nested");
+        fs::create_dir_all(&nested).unwrap();
+        assert!(watcher.poll().unwrap().is_empty());
+        fs::write(nested.join("copy"), b"World").unwrap();
+        let duplicates = watcher.poll().unwrap();
+        assert_eq!(duplicates.len(), 1);
+        assert!(duplicates[0].files.contains(&nested.join("copy")));
+
+        let moved = tempfile::tempdir().unwrap();
+        fs::write(moved.path().join("copy"), b"World").unwrap();
+        fs::rename(moved.path(), dir.path().join("moved")).unwrap();
+        let duplicates = watcher.poll().unwrap();
+        assert_eq!(duplicates.len(), 1);
+        assert!(duplicates[0]
+            .files
+            .contains(&dir.path().join("moved").join("copy")));
+    }
+
+    #[test]
+    fn rescan_after_overflow() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a");
+        fs::write(&a, b"Hello").unwrap();
+        fs::write(dir.path().join("b"), b"Hello").unwrap();
+        let mut watcher = Watcher::new(&[dir.path()]).unwrap();
+
+        // Duplicates present at start stay alone, as if no events were lost.
+        assert!(watcher.rescan(true).unwrap().is_empty());
+
+        let c = dir.path().join("c");
+        let d = dir.path().join("d");
+        fs::write(&c, b"World").unwrap();
+        fs::write(&d, b"World").unwrap();
+        fs::write(dir.path().join("e"), b"Unique").unwrap();
+        let duplicates = watcher.rescan(true).unwrap();
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(
+            HashSet::<_>::from_iter(duplicates[0].files.clone()),
+            HashSet::from_iter(vec![c.clone(), d.clone()])
+        );
+
+        let changed = dir.path().join("f");
+        fs::write(&changed, b"Other").unwrap();
+        assert!(watcher.rescan(true).unwrap().is_empty());
+        fs::write(&changed, b"Hello").unwrap();
+        let duplicates = watcher.rescan(true).unwrap();
+        assert_eq!(duplicates.len(), 1);
+        assert!(duplicates[0].files.contains(&changed));
+        assert!(duplicates[0].files.contains(&a));
+    }
+}
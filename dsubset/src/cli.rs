@@ -1,6 +1,12 @@
+use crate::compare::Match;
+use crate::manifest::Format;
 use pico_args as pico;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
 use std::path::PathBuf;
 use std::process;
+use std::str::FromStr;
 
 const HELP: &str = "\
 dsubset 0.8
@@ -8,17 +14,118 @@ jaan-c
 Check if SUBDIR's descendant files are a subset of SUPERDIR checksum.
 
 USAGE:
-    dsubset SUBDIR SUPERDIR
+    dsubset [OPTIONS] SUBDIR SUPERDIR...
+    dsubset manifest [OPTIONS] DIR -o FILE
+    dsubset verify [OPTIONS] MANIFEST [DIR]
+
+SUBDIR is checked against the union of all SUPERDIRs. SUBDIR and SUPERDIRs
+may also be manifests, whose listed hashes and paths are used instead of
+walking a directory.
+
+SUBCOMMANDS:
+    manifest            Write the SHA-256 hash of each file of DIR, with its
+                        path relative to DIR, to a manifest FILE.
+    verify              Rehash files listed by MANIFEST under DIR, which
+                        defaults to the directory of MANIFEST, printing each
+                        as ok, changed (modified since MANIFEST was written),
+                        corrupted (not modified, yet different), missing or
+                        new (not listed), tab separated with its path.
 
 OPTIONS:
     -h, --help          Displays help information.
-    -v, --version       Displays version information.";
+    -v, --version       Displays version information.
+    -m, --match [content, path, path+content]
+                        What must match for a SUBDIR file to be in SUPERDIR,
+                        its content anywhere, its path relative to SUBDIR or
+                        both. Defaults to content.
+    -r, --report        Classify every file of both directories, one per line,
+                        as both (same relative path and content), moved (same
+                        content elsewhere), modified (same relative path,
+                        different content), sub-only or super-only, tab
+                        separated with SUBDIR and SUPERDIR paths.
+    -q, --quiet         Print nothing, only set the exit status.
+    -c, --min-copies N  Require each SUBDIR file to be in at least N distinct
+                        SUPERDIRs, listing those it's in if fewer. Defaults
+                        to 1. Above 1, SUPERDIRs can't be the same or nested
+                        in one another.
+        --multiset      Also require SUPERDIRs to have as many copies of each
+                        SUBDIR file as SUBDIR has, each copy only making up
+                        for one, listing hashes short of copies. In a report
+                        they're listed as shortfall, tab separated with hash
+                        and counts of copies in SUBDIR and SUPERDIRs.
+        --copy-missing DEST
+                        Copy SUBDIR files short of copies to their relative
+                        paths under DEST, unless DEST is a SUPERDIR they're
+                        in, with their modification times. An identical file
+                        already there is left as is, and a different one is
+                        never overwritten, the copy is suffixed with (1), (2),
+                        ... instead. Copies are checked against their hashes.
+                        Doesn't change the exit status.
+        --delete-present
+                        Delete SUBDIR files whose hash is in enough SUPERDIRs,
+                        as set by --min-copies, outside SUBDIR, leaving the
+                        rest untouched. SUPERDIRs must be directories, not
+                        manifests. Requires --journal.
+        --journal FILE  Append a line per deleted file to FILE: seconds since
+                        the epoch, hash, path and SUPERDIRs holding its hash,
+                        tab separated.
+        --verify-bytes  Only delete a SUBDIR file once a copy of it in a
+                        SUPERDIR has identical bytes.
+    -d, --dry-run       Print what --copy-missing or --delete-present would
+                        copy or delete without doing so.
+
+MANIFEST OPTIONS:
+    -o, --output FILE   Manifest file to write.
+    -f, --format [gnu, bsd]
+                        Write lines as sha256sum does, HASH  PATH, or as
+                        sha256sum --tag does, SHA256 (PATH) = HASH. Both are
+                        read. Defaults to gnu.
+
+VERIFY OPTIONS:
+    -u, --update        Rewrite MANIFEST with new hashes of changed files.
+
+EXIT STATUS:
+    0   SUBDIR is a subset of SUPERDIRs, or all verified files are ok or
+        updated.
+    1   SUBDIR is not a subset of SUPERDIRs, or verified files are not ok.
+    2   Invalid arguments.
+    3   Files could not be read, so the result may be wrong.";
 const VERSION: &str = "dsubset 0.8";
 
+#[derive(Debug)]
+pub enum Command {
+    /// Check whether SUBDIR is a subset of SUPERDIR.
+    Compare {
+        sub_dir: PathBuf,
+        super_dirs: Vec<PathBuf>,
+    },
+    /// Write a manifest of `dir` to `output`.
+    Manifest {
+        dir: PathBuf,
+        output: PathBuf,
+        format: Format,
+    },
+    /// Verify files under `dir` against `manifest`.
+    Verify {
+        manifest: PathBuf,
+        dir: Option<PathBuf>,
+        update: bool,
+    },
+}
+
 #[derive(Debug)]
 pub struct Args {
-    pub sub_dir: PathBuf,
-    pub super_dir: PathBuf,
+    pub command: Command,
+    pub match_mode: Match,
+    pub report: bool,
+    pub quiet: bool,
+    pub min_copies: usize,
+    pub multiset: bool,
+    pub copy_missing: Option<PathBuf>,
+    pub delete_present: bool,
+    pub journal: Option<PathBuf>,
+    pub verify_bytes: bool,
+    pub dry_run: bool,
 }
 
 pub fn get_args() -> Args {
@@ -26,14 +133,22 @@ pub fn get_args() -> Args {
         Ok(args) => args,
         Err(err) => {
             eprintln!("{}", err);
-            print!("{}", HELP);
-            process::exit(0);
+            eprint!("{}", HELP);
+            process::exit(crate::USAGE_ERROR);
         }
     }
 }
 
 fn parse_args() -> Result<Args, pico::Error> {
-    let mut pargs = pico::Arguments::from_env();
+    let mut args = env::args_os().skip(1).collect::<Vec<OsString>>();
+    let subcommand = match args.first().and_then(|a| a.to_str()) {
+        Some(s @ "manifest") | Some(s @ "verify") => Some(s.to_string()),
+        _ => None,
+    };
+    if subcommand.is_some() {
+        args.remove(0);
+    }
+    let mut pargs = pico::Arguments::from_vec(args);
 
     if pargs.contains(["-h", "--help"]) {
         print!("{}", HELP);
@@ -43,17 +158,169 @@ fn parse_args() -> Result<Args, pico::Error> {
         process::exit(0);
     }
 
+    let match_mode = pargs
+        .opt_value_from_str(["-m", "--match"])?
+        .unwrap_or(Match::Content);
+    let report = pargs.contains(["-r", "--report"]);
+    let quiet = pargs.contains(["-q", "--quiet"]);
+    let min_copies = pargs
+        .opt_value_from_fn(["-c", "--min-copies"], parse_min_copies)?
+        .unwrap_or(1);
+    let multiset = pargs.contains("--multiset");
+    let copy_missing = pargs.opt_value_from_str("--copy-missing")?;
+    let delete_present = pargs.contains("--delete-present");
+    let journal = if delete_present {
+        Some(pargs.value_from_str("--journal")?)
+    } else {
+        pargs.opt_value_from_str("--journal")?
+    };
+    let verify_bytes = pargs.contains("--verify-bytes");
+    let dry_run = pargs.contains(["-d", "--dry-run"]);
+
+    // Free arguments come last, once options can't be mistaken for them.
+    let command = match subcommand.as_deref() {
+        Some("manifest") => {
+            let output = pargs.value_from_str(["-o", "--output"])?;
+            let format = pargs
+                .opt_value_from_str(["-f", "--format"])?
+                .unwrap_or(Format::Gnu);
+            Command::Manifest {
+                dir: pargs.free_from_str()?,
+                output,
+                format,
+            }
+        }
+        Some(_) => {
+            let update = pargs.contains(["-u", "--update"]);
+            Command::Verify {
+                manifest: pargs.free_from_str()?,
+                dir: pargs.opt_free_from_str()?,
+                update,
+            }
+        }
+        None => {
+            let sub_dir = pargs.free_from_str()?;
+            let mut super_dirs = vec![pargs.free_from_str()?];
+            while let Some(super_dir) = pargs.opt_free_from_str()? {
+                super_dirs.push(super_dir);
+            }
+            Command::Compare {
+                sub_dir,
+                super_dirs,
+            }
+        }
+    };
+
     let args = Args {
-        sub_dir: pargs.free_from_str()?,
-        super_dir: pargs.free_from_str()?,
+        command,
+        match_mode,
+        report,
+        quiet,
+        min_copies,
+        multiset,
+        copy_missing,
+        delete_present,
+        journal,
+        verify_bytes,
+        dry_run,
     };
 
     let remaining = pargs.finish();
     if !remaining.is_empty() {
         eprintln!("Invalid excess arguments: {:?}", remaining);
-        println!("{}", HELP);
-        process::exit(0);
+        eprint!("{}", HELP);
+        process::exit(crate::USAGE_ERROR);
+    }
+
+    // Manifests can't be checked to still hold the files they list.
+    if let Command::Compare { super_dirs, .. } = &args.command {
+        if args.delete_present && super_dirs.iter().any(|d| d.is_file()) {
+            eprintln!("--delete-present needs SUPERDIR directories, not manifests.");
+            eprint!("{}", HELP);
+            process::exit(crate::USAGE_ERROR);
+        }
+        // A copy in nested SUPERDIRs would count once for each of them.
+        if args.min_copies > 1 && overlapping(super_dirs) {
+            eprintln!("--min-copies above 1 needs distinct SUPERDIRs, not nested ones.");
+            eprint!("{}", HELP);
+            process::exit(crate::USAGE_ERROR);
+        }
     }
 
     Ok(args)
 }
+
+/// Whether any of `super_dirs` are the same or, if directories, nested in
+/// one another.
+fn overlapping(super_dirs: &[PathBuf]) -> bool {
+    let canonical = super_dirs
+        .iter()
+        .map(|d| {
+            (
+                fs::canonicalize(d).unwrap_or_else(|_| d.clone()),
+                d.is_dir(),
+            )
+        })
+        .collect::<Vec<(PathBuf, bool)>>();
+    canonical.iter().enumerate().any(|(i, (a, a_dir))| {
+        canonical[i + 1..].iter().any(|(b, b_dir)| {
+            a == b || (*a_dir && *b_dir && (a.starts_with(b) || b.starts_with(a)))
+        })
+    })
+}
+
+fn parse_min_copies(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(0) => Err("--min-copies must be at least 1.".to_string()),
+        Ok(n) => Ok(n),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+impl FromStr for Match {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "content" => Ok(Match::Content),
+            "path" => Ok(Match::Path),
+            "path+content" => Ok(Match::PathContent),
+            _ => Err(format!("invalid match '{}'.", s)),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gnu" => Ok(Format::Gnu),
+            "bsd" => Ok(Format::Bsd),
+            _ => Err(format!("invalid format '{}'.", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cli;
+    use std::fs;
+
+    #[test]
+    fn overlapping_super_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a");
+        let b = dir.path().join("b");
+        let nested = a.join("nested");
+        let similar = dir.path().join("ab");
+        for d in [&a, &b, &nested, &similar] {
+            fs::create_dir(d).unwrap();
+        }
+
+        assert!(!cli::overlapping(&[a.clone(), b.clone(), similar]));
+        assert!(cli::overlapping(&[a.clone(), b.clone(), a.join(".")]));
+        assert!(cli::overlapping(&[a.clone(), b.join("../a/")]));
+        assert!(cli::overlapping(&[b, nested, a]));
+    }
+}
@@ -0,0 +1,275 @@
+use hashbrown::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Files of a directory tree, looked up by content hash and by path relative
+/// to its root.
+///
+/// Files without a hash are known to share content with no file they're
+/// compared to.
+pub struct Tree {
+    root: PathBuf,
+    by_hash: HashMap<String, Vec<PathBuf>>,
+    by_path: HashMap<PathBuf, (PathBuf, Option<String>)>,
+}
+
+/// What must match for a SUBDIR file to be present in SUPERDIR.
+#[derive(Debug, Clone, Copy)]
+pub enum Match {
+    /// Same content anywhere.
+    Content,
+    /// Same relative path, whatever the content.
+    Path,
+    /// Same content at the same relative path.
+    PathContent,
+}
+
+/// Where a file was found, as classified by [`compare`].
+pub enum Comparison {
+    /// Same content at the same relative path.
+    Both {
+        sub: PathBuf,
+        sup: PathBuf,
+    },
+    /// Content of a SUBDIR file found in SUPERDIR only at other paths, all of
+    /// `supers`.
+    Moved {
+        sub: PathBuf,
+        supers: Vec<PathBuf>,
+    },
+    SubOnly(PathBuf),
+    SuperOnly(PathBuf),
+    /// Files at the same relative path with different content.
+    Modified {
+        sub: PathBuf,
+        sup: PathBuf,
+    },
+}
+
+impl Tree {
+    /// Index `files` of `root` along with their hashes, if any.
+    pub fn new<P, I>(root: P, files: I) -> Tree
+    where
+        P: AsRef<Path>,
+        I: IntoIterator<Item = (PathBuf, Option<String>)>,
+    {
+        let mut tree = Tree {
+            root: root.as_ref().to_path_buf(),
+            by_hash: HashMap::new(),
+            by_path: HashMap::new(),
+        };
+
+        for (file, hash) in files {
+            tree.insert(file, hash);
+        }
+
+        tree
+    }
+
+    /// Index `file` along with its hash, if any, replacing a file at the same
+    /// relative path indexed without one.
+    pub fn insert(&mut self, file: PathBuf, hash: Option<String>) {
+        let relative = relative(&self.root, &file).to_path_buf();
+        if let Some(hash) = &hash {
+            self.by_hash
+                .entry(hash.clone())
+                .or_insert_with(Vec::new)
+                .push(file.clone());
+        }
+        self.by_path.insert(relative, (file, hash));
+    }
+
+    pub fn contains_hash(&self, hash: Option<&String>) -> bool {
+        hash.is_some_and(|h| self.by_hash.contains_key(h))
+    }
+
+    /// Files with `hash`.
+    pub fn files_with_hash(&self, hash: &str) -> &[PathBuf] {
+        self.by_hash.get(hash).map_or(&[], Vec::as_slice)
+    }
+
+    /// Whether a file at `relative` path with `hash` is present by `mode`.
+    pub fn contains(&self, relative: &Path, hash: Option<&String>, mode: Match) -> bool {
+        match mode {
+            Match::Content => self.contains_hash(hash),
+            Match::Path => self.by_path.contains_key(relative),
+            Match::PathContent => self
+                .by_path
+                .get(relative)
+                .is_some_and(|(_, h)| same_content(h.as_ref(), hash)),
+        }
+    }
+}
+
+fn same_content(a: Option<&String>, b: Option<&String>) -> bool {
+    a.is_some() && a == b
+}
+
+/// Path of `file` relative to `root`, or `file` itself if it isn't under it.
+pub fn relative<'a>(root: &Path, file: &'a Path) -> &'a Path {
+    file.strip_prefix(root).unwrap_or(file)
+}
+
+/// Classify every file of `sub` and `supers`, sorted by path, SUPERDIR files
+/// in order of `supers`.
+///
+/// Files are first matched by content at the same relative path, then by
+/// content anywhere in the other tree and finally by relative path alone, so
+/// a file is only modified if its content isn't found elsewhere either. A
+/// SUBDIR file is classified against each of `supers` that has it.
+pub fn compare(sub: &Tree, supers: &[Tree]) -> Vec<Comparison> {
+    let mut sub_files = sub.by_path.iter().collect::<Vec<_>>();
+    sub_files.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut comparisons = vec![];
+    for (relative, (file, hash)) in sub_files {
+        let mut found = vec![];
+        let mut modified = vec![];
+        for sup in supers {
+            match classify(relative, file, hash.as_ref(), sup) {
+                Some(c @ Comparison::Modified { .. }) => modified.push(c),
+                Some(c) => found.push(c),
+                None => {}
+            }
+        }
+
+        if !found.is_empty() {
+            comparisons.extend(found);
+        } else if !modified.is_empty() {
+            comparisons.extend(modified);
+        } else {
+            comparisons.push(Comparison::SubOnly(file.clone()));
+        }
+    }
+
+    for sup in supers {
+        let mut super_files = sup.by_path.iter().collect::<Vec<_>>();
+        super_files.sort_by(|a, b| a.0.cmp(b.0));
+        for (relative, (file, hash)) in super_files {
+            if !sub.contains_hash(hash.as_ref()) && !sub.by_path.contains_key(relative) {
+                comparisons.push(Comparison::SuperOnly(file.clone()));
+            }
+        }
+    }
+
+    comparisons
+}
+
+/// Classify a SUBDIR file against `sup`, or `None` if `sup` doesn't have it.
+fn classify(relative: &Path, file: &Path, hash: Option<&String>, sup: &Tree) -> Option<Comparison> {
+    let supers = hash.and_then(|h| sup.by_hash.get(h));
+    match (supers, sup.by_path.get(relative)) {
+        (_, Some((sup_file, sup_hash))) if same_content(sup_hash.as_ref(), hash) => {
+            Some(Comparison::Both {
+                sub: file.to_path_buf(),
+                sup: sup_file.clone(),
+            })
+        }
+        (Some(supers), _) => Some(Comparison::Moved {
+            sub: file.to_path_buf(),
+            supers: supers.clone(),
+        }),
+        (None, Some((sup_file, _))) => Some(Comparison::Modified {
+            sub: file.to_path_buf(),
+            sup: sup_file.clone(),
+        }),
+        (None, None) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compare::{self, Comparison, Match, Tree};
+    use std::path::Path;
+    use std::path::PathBuf;
+
+    fn tree(root: &str, files: &[(&str, &str)]) -> Tree {
+        Tree::new(
+            root,
+            files
+                .iter()
+                .map(|(f, h)| (PathBuf::from(root).join(f), Some(h.to_string()))),
+        )
+    }
+
+    fn describe(comparisons: &[Comparison]) -> Vec<String> {
+        comparisons
+            .iter()
+            .map(|c| match c {
+                Comparison::Both { sub, sup } => {
+                    format!("both {} {}", sub.display(), sup.display())
+                }
+                Comparison::Moved { sub, supers } => {
+                    let supers = supers.iter().map(|s| s.display().to_string());
+                    format!(
+                        "moved {} {}",
+                        sub.display(),
+                        supers.collect::<Vec<_>>().join(",")
+                    )
+                }
+                Comparison::SubOnly(f) => format!("sub-only {}", f.display()),
+                Comparison::SuperOnly(f) => format!("super-only {}", f.display()),
+                Comparison::Modified { sub, sup } => {
+                    format!("modified {} {}", sub.display(), sup.display())
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn compare_trees() {
+        let sub = tree("sub", &[("same", "1"), ("changed", "2"), ("new", "3")]);
+        let sup = tree("sup", &[("same", "1"), ("changed", "4"), ("old", "5")]);
+
+        assert_eq!(
+            describe(&compare::compare(&sub, &[sup])),
+            [
+                "modified sub/changed sup/changed",
+                "sub-only sub/new",
+                "both sub/same sup/same",
+                "super-only sup/old",
+            ]
+        );
+    }
+
+    #[test]
+    fn modified_only_if_found_nowhere() {
+        let sub = tree("sub", &[("a", "1")]);
+        let changed = tree("sup1", &[("a", "2")]);
+        let same = tree("sup2", &[("a", "1")]);
+
+        assert_eq!(
+            describe(&compare::compare(&sub, &[changed, same])),
+            ["both sub/a sup2/a"]
+        );
+    }
+
+    #[test]
+    fn moved_before_modified() {
+        let sub = tree("sub", &[("a", "1"), ("b", "2")]);
+        let sup = tree("sup", &[("a", "2"), ("c", "1"), ("d", "1")]);
+
+        assert_eq!(
+            describe(&compare::compare(&sub, &[sup])),
+            ["moved sub/a sup/c,sup/d", "moved sub/b sup/a"]
+        );
+    }
+
+    #[test]
+    fn match_modes() {
+        let sup = tree("sup", &[("a", "1"), ("b", "2")]);
+        let hash = |h: &str| Some(h.to_string());
+
+        let cases = [
+            ("a", hash("1"), [true, true, true]),
+            ("a", hash("2"), [true, true, false]),
+            ("c", hash("1"), [true, false, false]),
+            ("a", hash("3"), [false, true, false]),
+            ("a", None, [false, true, false]),
+        ];
+        for (relative, hash, expected) in cases {
+            let found = [Match::Content, Match::Path, Match::PathContent]
+                .map(|mode| sup.contains(Path::new(relative), hash.as_ref(), mode));
+            assert_eq!(found, expected, "{} {:?}", relative, hash);
+        }
+    }
+}
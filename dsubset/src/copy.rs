@@ -0,0 +1,148 @@
+use crate::hash;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where a file was copied to.
+#[derive(Debug)]
+pub enum Copied {
+    /// Copied to a new file, or would be on a dry run.
+    To(PathBuf),
+    /// Already present as an identical file, left as is.
+    Present(PathBuf),
+}
+
+/// Copy `file` of `hash`, if known, to `target` along with its modification
+/// time, then check the copy against the hash.
+///
+/// An existing different file at `target` is never overwritten, the copy
+/// goes next to it instead with a ` (1)`, ` (2)`, ... suffix.
+pub fn file<P, Q>(file: P, hash: Option<&str>, target: Q, dry_run: bool) -> io::Result<Copied>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let hash = match hash {
+        Some(hash) => hash.to_string(),
+        None => hash::file(&file)?,
+    };
+
+    let mut suffix = 0;
+    let target = loop {
+        let candidate = with_suffix(target.as_ref(), suffix);
+        if !candidate.exists() {
+            break candidate;
+        } else if candidate.is_file() && hash::file(&candidate)? == hash {
+            return Ok(Copied::Present(candidate));
+        }
+        suffix += 1;
+    };
+
+    if dry_run {
+        return Ok(Copied::To(target));
+    }
+
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut source = File::open(&file)?;
+    let mut copy = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&target)?;
+    io::copy(&mut source, &mut copy)?;
+    copy.set_modified(source.metadata()?.modified()?)?;
+    copy.sync_all()?;
+
+    if hash::file(&target)? != hash {
+        fs::remove_file(&target)?;
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "copy of {} to {} doesn't match its hash",
+                file.as_ref().display(),
+                target.display()
+            ),
+        ));
+    }
+
+    Ok(Copied::To(target))
+}
+
+/// `path` with ` (suffix)` after its file stem, or as is if `suffix` is 0.
+fn with_suffix(path: &Path, suffix: u32) -> PathBuf {
+    if suffix == 0 {
+        return path.to_path_buf();
+    }
+
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(format!(" ({})", suffix));
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::copy::{self, Copied};
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn suffix_paths() {
+        let suffixed = |path: &str, suffix| copy::with_suffix(Path::new(path), suffix);
+        assert_eq!(suffixed("a/b.txt", 0), Path::new("a/b.txt"));
+        assert_eq!(suffixed("a/b.txt", 1), Path::new("a/b (1).txt"));
+        assert_eq!(suffixed("a/b.tar.gz", 2), Path::new("a/b.tar (2).gz"));
+        assert_eq!(suffixed("a/b", 1), Path::new("a/b (1)"));
+        assert_eq!(suffixed("a/.profile", 1), Path::new("a/.profile (1)"));
+    }
+
+    #[test]
+    fn copy_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source.txt");
+        fs::write(&source, b"Hello").unwrap();
+        let target = dir.path().join("dest").join("source.txt");
+
+        match copy::file(&source, None, &target, true).unwrap() {
+            Copied::To(copy) => assert_eq!(copy, target),
+            copied => panic!("unexpected {:?}", copied),
+        }
+        assert!(!target.exists());
+
+        match copy::file(&source, None, &target, false).unwrap() {
+            Copied::To(copy) => assert_eq!(copy, target),
+            copied => panic!("unexpected {:?}", copied),
+        }
+        assert_eq!(fs::read(&target).unwrap(), b"Hello");
+        assert_eq!(
+            fs::metadata(&target).unwrap().modified().unwrap(),
+            fs::metadata(&source).unwrap().modified().unwrap()
+        );
+
+        match copy::file(&source, None, &target, false).unwrap() {
+            Copied::Present(copy) => assert_eq!(copy, target),
+            copied => panic!("unexpected {:?}", copied),
+        }
+    }
+
+    #[test]
+    fn copy_next_to_different_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source.txt");
+        fs::write(&source, b"Hello").unwrap();
+        let target = dir.path().join("target.txt");
+        fs::write(&target, b"World").unwrap();
+
+        let suffixed = dir.path().join("target (1).txt");
+        match copy::file(&source, None, &target, false).unwrap() {
+            Copied::To(copy) => assert_eq!(copy, suffixed),
+            copied => panic!("unexpected {:?}", copied),
+        }
+        assert_eq!(fs::read(&target).unwrap(), b"World");
+        assert_eq!(fs::read(&suffixed).unwrap(), b"Hello");
+    }
+}
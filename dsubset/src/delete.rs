@@ -0,0 +1,289 @@
+use crate::compare::Tree;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// A SUBDIR file whose hash was found in enough SUPERDIRs to be deleted.
+#[derive(Debug)]
+pub struct Present {
+    pub file: PathBuf,
+    pub hash: String,
+    /// SUPERDIRs holding the hash.
+    pub holders: Vec<PathBuf>,
+    /// Files of walked SUPERDIRs with the hash, outside SUBDIR.
+    pub copies: Vec<PathBuf>,
+}
+
+/// `file` of `hash` as present, if at least `min_copies`, and at least one,
+/// of `holders` have its hash outside of `sub_dir`, a canonical path.
+pub fn find_present(
+    file: &Path,
+    hash: &str,
+    holders: &[(&PathBuf, &Tree)],
+    sub_dir: &Path,
+    min_copies: usize,
+) -> Option<Present> {
+    let mut present = Present {
+        file: file.to_path_buf(),
+        hash: hash.to_string(),
+        holders: vec![],
+        copies: vec![],
+    };
+
+    for (super_dir, tree) in holders {
+        let copies = tree
+            .files_with_hash(hash)
+            .iter()
+            .filter(|c| is_outside(c, sub_dir))
+            .cloned()
+            .collect::<Vec<PathBuf>>();
+        if !copies.is_empty() {
+            present.holders.push(super_dir.to_path_buf());
+            present.copies.extend(copies);
+        }
+    }
+
+    if !present.holders.is_empty() && present.holders.len() >= min_copies {
+        Some(present)
+    } else {
+        None
+    }
+}
+
+/// Whether `copy` exists outside of `sub_dir`, a canonical path.
+pub fn is_outside(copy: &Path, sub_dir: &Path) -> bool {
+    fs::canonicalize(copy).is_ok_and(|c| !c.starts_with(sub_dir))
+}
+
+/// Deleter of present files, appending a line per deleted file to a journal
+/// before deleting it: the time in seconds since the epoch, the hash, the
+/// file and the SUPERDIRs holding its hash, tab separated.
+pub struct Deleter {
+    /// `None` on a dry run.
+    journal: Option<File>,
+    verify: bool,
+}
+
+impl Deleter {
+    /// Open `journal` to append to unless `dry_run`. Files are only deleted
+    /// after a copy is found with identical bytes if `verify`.
+    pub fn new<P>(journal: P, verify: bool, dry_run: bool) -> io::Result<Deleter>
+    where
+        P: AsRef<Path>,
+    {
+        let journal = if dry_run {
+            None
+        } else {
+            Some(OpenOptions::new().create(true).append(true).open(journal)?)
+        };
+
+        Ok(Deleter { journal, verify })
+    }
+
+    /// Delete and record `present`, returning whether it was deleted, or would
+    /// be on a dry run.
+    pub fn delete(&mut self, present: &Present) -> io::Result<bool> {
+        if self.verify {
+            let mut verified = false;
+            for copy in present.copies.iter() {
+                if same_bytes(&present.file, copy)? {
+                    verified = true;
+                    break;
+                }
+            }
+            if !verified {
+                return Ok(false);
+            }
+        }
+
+        let journal = match self.journal.as_mut() {
+            Some(journal) => journal,
+            None => return Ok(true),
+        };
+
+        // Recorded first, so no file is ever deleted without a record.
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let holders = present
+            .holders
+            .iter()
+            .map(|h| h.display().to_string())
+            .collect::<Vec<String>>();
+        writeln!(
+            journal,
+            "{}\t{}\t{}\t{}",
+            time,
+            present.hash,
+            present.file.display(),
+            holders.join("\t")
+        )?;
+        journal.sync_data()?;
+        fs::remove_file(&present.file)?;
+
+        Ok(true)
+    }
+}
+
+/// Whether files `a` and `b` have identical bytes.
+fn same_bytes<P, Q>(a: P, b: Q) -> io::Result<bool>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let mut a = File::open(a)?;
+    let mut b = File::open(b)?;
+    if a.metadata()?.len() != b.metadata()?.len() {
+        return Ok(false);
+    }
+
+    let mut a_buf = vec![0; BUFFER_SIZE];
+    let mut b_buf = vec![0; BUFFER_SIZE];
+    loop {
+        let read = read_full(&mut a, &mut a_buf)?;
+        if read != read_full(&mut b, &mut b_buf)? || a_buf[..read] != b_buf[..read] {
+            return Ok(false);
+        } else if read == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/// Read until `buf` is full or the end of `file`, returning bytes read.
+fn read_full(file: &mut File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut count = 0;
+    while count < buf.len() {
+        match file.read(&mut buf[count..])? {
+            0 => break,
+            read => count += read,
+        }
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compare::Tree;
+    use crate::delete::{self, Deleter, Present};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    fn present(file: &Path, copies: &[&Path]) -> Present {
+        Present {
+            file: file.to_path_buf(),
+            hash: "abc".to_string(),
+            holders: vec![PathBuf::from("super")],
+            copies: copies.iter().map(|c| c.to_path_buf()).collect(),
+        }
+    }
+
+    #[test]
+    fn find_present_outside_sub_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = fs::canonicalize(dir.path()).unwrap();
+        let sub_dir = dir.join("sub");
+        fs::create_dir(&sub_dir).unwrap();
+        let file = sub_dir.join("a");
+        let inside = sub_dir.join("b");
+        let outside = dir.join("c");
+        for f in [&file, &inside, &outside] {
+            fs::write(f, b"Hello").unwrap();
+        }
+
+        let hashed = |files: Vec<&PathBuf>| {
+            let files = files
+                .into_iter()
+                .map(|f| (f.clone(), Some("abc".to_string())));
+            Tree::new(&dir, files.collect::<Vec<_>>())
+        };
+        let within = hashed(vec![&file, &inside]);
+        let both = hashed(vec![&file, &inside, &outside]);
+
+        let holders = [(&dir, &within)];
+        assert!(delete::find_present(&file, "abc", &holders, &sub_dir, 1).is_none());
+
+        let holders = [(&dir, &both)];
+        let found = delete::find_present(&file, "abc", &holders, &sub_dir, 1).unwrap();
+        assert_eq!(found.holders, vec![dir.clone()]);
+        assert_eq!(found.copies, vec![outside.clone()]);
+        assert!(delete::find_present(&file, "abc", &holders, &sub_dir, 2).is_none());
+        assert!(delete::find_present(&file, "abc", &[], &sub_dir, 0).is_none());
+    }
+
+    #[test]
+    fn delete_and_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = dir.path().join("journal");
+        let file = dir.path().join("a");
+        let copy = dir.path().join("b");
+        fs::write(&file, b"Hello").unwrap();
+        fs::write(&copy, b"Hello").unwrap();
+
+        let mut deleter = Deleter::new(&journal, false, false).unwrap();
+        assert!(deleter.delete(&present(&file, &[])).unwrap());
+        assert!(!file.exists());
+
+        let journal = fs::read_to_string(&journal).unwrap();
+        let fields = journal.trim_end().split('\t').collect::<Vec<&str>>();
+        assert_eq!(&fields[1..], ["abc", &file.display().to_string(), "super"]);
+    }
+
+    #[test]
+    fn delete_verified() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = dir.path().join("journal");
+        let file = dir.path().join("a");
+        let same = dir.path().join("b");
+        let different = dir.path().join("c");
+        fs::write(&file, b"Hello").unwrap();
+        fs::write(&same, b"Hello").unwrap();
+        fs::write(&different, b"World").unwrap();
+
+        let mut deleter = Deleter::new(&journal, true, false).unwrap();
+        assert!(!deleter.delete(&present(&file, &[&different])).unwrap());
+        assert!(file.exists());
+        assert_eq!(fs::read(&journal).unwrap(), b"");
+
+        assert!(deleter
+            .delete(&present(&file, &[&different, &same]))
+            .unwrap());
+        assert!(!file.exists());
+    }
+
+    #[test]
+    fn dry_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = dir.path().join("journal");
+        let file = dir.path().join("a");
+        fs::write(&file, b"Hello").unwrap();
+
+        let mut deleter = Deleter::new(&journal, false, true).unwrap();
+        assert!(deleter.delete(&present(&file, &[])).unwrap());
+        assert!(file.exists());
+        assert!(!journal.exists());
+    }
+
+    #[test]
+    fn compare_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, content: &[u8]| {
+            let path = dir.path().join(name);
+            fs::write(&path, content).unwrap();
+            path
+        };
+        let large = vec![7; delete::BUFFER_SIZE * 2 + 1];
+        let mut changed = large.clone();
+        *changed.last_mut().unwrap() = 8;
+
+        let a = write("a", &large);
+        assert!(delete::same_bytes(&a, write("b", &large)).unwrap());
+        assert!(!delete::same_bytes(&a, write("c", &changed)).unwrap());
+        assert!(!delete::same_bytes(&a, write("d", &large[1..])).unwrap());
+        assert!(delete::same_bytes(write("e", b""), write("f", b"")).unwrap());
+    }
+}
@@ -1,3 +1,4 @@
+use crate::Output;
 use std::fs;
 use std::io;
 use std::iter::Iterator;
@@ -5,25 +6,27 @@ use std::path::{Path, PathBuf};
 
 // An [Iterator] that yields all descendant files of a directory.
 //
-// Symlinks are silently ignored.
-pub struct FileWalker {
+// Symlinks are ignored, printing each to `out`.
+pub struct FileWalker<'a> {
     pending_yield: Vec<io::Result<PathBuf>>,
     parents: Vec<PathBuf>,
+    out: &'a Output,
 }
 
-impl FileWalker {
-    pub fn new<P>(directory: P) -> FileWalker
+impl<'a> FileWalker<'a> {
+    pub fn new<P>(directory: P, out: &'a Output) -> FileWalker<'a>
     where
         P: AsRef<Path>,
     {
         FileWalker {
             pending_yield: vec![],
             parents: vec![directory.as_ref().to_path_buf()],
+            out,
         }
     }
 }
 
-impl Iterator for FileWalker {
+impl Iterator for FileWalker<'_> {
     type Item = io::Result<PathBuf>;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -39,12 +42,12 @@ impl Iterator for FileWalker {
         let mut new_pending_yield = vec![] as Vec<io::Result<PathBuf>>;
         let mut new_parents = vec![];
         for p in self.parents.iter() {
-            let (files, dirs, errs) = partition_directory_children(p);
+            let (files, dirs, errs) = partition_directory_children(p, self.out);
 
             new_parents.extend(dirs);
 
-            new_pending_yield.extend(files.into_iter().map(|f| Ok(f)));
-            new_pending_yield.extend(errs.into_iter().map(|e| Err(e)));
+            new_pending_yield.extend(files.into_iter().map(Ok));
+            new_pending_yield.extend(errs.into_iter().map(Err));
         }
 
         self.pending_yield = new_pending_yield;
@@ -55,7 +58,10 @@ impl Iterator for FileWalker {
 }
 
 /// Partition children of directory into a tuple (files, dirs, errors).
-fn partition_directory_children<P>(directory: P) -> (Vec<PathBuf>, Vec<PathBuf>, Vec<io::Error>)
+fn partition_directory_children<P>(
+    directory: P,
+    out: &Output,
+) -> (Vec<PathBuf>, Vec<PathBuf>, Vec<io::Error>)
 where
     P: AsRef<Path>,
 {
@@ -77,7 +83,7 @@ where
                     } else if metadata.is_file() {
                         files.push(entry.path());
                     } else {
-                        println!("Ignoring symlink {}", entry.path().display());
+                        out.print(format_args!("Ignoring symlink {}", entry.path().display()));
                     }
                 }
                 Err(err) => errors.push(err),
@@ -89,9 +95,9 @@ where
     (files, dirs, errors)
 }
 
-pub fn walk_files<P>(directory: P) -> FileWalker
+pub fn walk_files<P>(directory: P, out: &Output) -> FileWalker<'_>
 where
     P: AsRef<Path>,
 {
-    FileWalker::new(directory)
+    FileWalker::new(directory, out)
 }
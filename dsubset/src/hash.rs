@@ -1,11 +1,11 @@
 use crypto_hash::{self, Algorithm, Hasher};
-use hex;
 use std::fs::File;
 use std::io;
 use std::io::{BufReader, Read, Write};
 use std::path::Path;
 
 const BUFFER_SIZE: usize = 4096;
+const HEAD_SIZE: usize = 4_000;
 const HASH_ALGORITHM: Algorithm = Algorithm::SHA256;
 
 pub fn file<P>(path: P) -> io::Result<String>
@@ -25,3 +25,23 @@ where
 
     Ok(hex::encode(hasher.finish()))
 }
+
+/// Hash only the first few kilobytes of a file, to cheaply tell apart files
+/// of the same size.
+pub fn head<P>(path: P) -> io::Result<String>
+where
+    P: AsRef<Path>,
+{
+    let mut buf = [0; HEAD_SIZE];
+    let mut reader = File::open(&path)?.take(HEAD_SIZE as u64);
+
+    let mut count = 0;
+    loop {
+        match reader.read(&mut buf[count..])? {
+            0 => break,
+            read => count += read,
+        }
+    }
+
+    Ok(crypto_hash::hex_digest(HASH_ALGORITHM, &buf[..count]))
+}
@@ -0,0 +1,145 @@
+use crate::compare::Tree;
+use crate::{hash, Candidate, Candidates, Output};
+use hashbrown::{HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A walked SUPERDIR file not hashed yet, along with the index of its tree.
+type Pending = (usize, PathBuf);
+
+/// Trees of SUPERDIRs whose walked files are only hashed once a SUBDIR file
+/// may share their content, so SUBDIR can be streamed past them instead of
+/// being collected first.
+pub struct LazyTrees {
+    trees: Vec<Tree>,
+    /// Whether every SUPERDIR was walked, so SUBDIR files can be ruled out
+    /// by size and head.
+    walked: bool,
+    sizes: HashSet<u64>,
+    heads: HashSet<(u64, String)>,
+    /// Files not hashed yet by size, then by size and head once their heads
+    /// are hashed.
+    by_size: HashMap<u64, Vec<Pending>>,
+    by_head: HashMap<(u64, String), Vec<Pending>>,
+}
+
+impl LazyTrees {
+    /// Index files of each of `roots`, walked ones without a hash for now.
+    pub fn new(roots: &[&Path], files: Vec<Candidates>) -> LazyTrees {
+        let mut lazy = LazyTrees {
+            trees: vec![],
+            walked: true,
+            sizes: HashSet::new(),
+            heads: HashSet::new(),
+            by_size: HashMap::new(),
+            by_head: HashMap::new(),
+        };
+
+        for (i, (root, files)) in roots.iter().zip(files).enumerate() {
+            let mut tree = Tree::new(root, vec![]);
+            for (f, candidate) in files {
+                match candidate {
+                    Candidate::Sized(size) => {
+                        lazy.sizes.insert(size);
+                        lazy.by_size.entry(size).or_default().push((i, f.clone()));
+                        tree.insert(f, None);
+                    }
+                    Candidate::Hashed(h) => {
+                        lazy.walked = false;
+                        tree.insert(f, Some(h));
+                    }
+                    Candidate::Unique | Candidate::Head(..) => tree.insert(f, None),
+                }
+            }
+            lazy.trees.push(tree);
+        }
+
+        lazy
+    }
+
+    pub fn trees(&self) -> &[Tree] {
+        &self.trees
+    }
+
+    /// Hash a SUBDIR `file` of `size` bytes, along with SUPERDIR files that
+    /// may share its content, or `None` if none can. Failures to hash
+    /// SUPERDIR files are logged to `out`, leaving them without a hash.
+    pub fn hash(&mut self, file: &Path, size: u64, out: &Output) -> io::Result<Option<String>> {
+        // Manifests list files of any size, which can't be ruled out.
+        if !self.walked {
+            let hash = hash::file(file)?;
+            let pending = self.by_size.remove(&size).unwrap_or_default();
+            self.hash_files(pending, out);
+            return Ok(Some(hash));
+        }
+
+        if !self.sizes.contains(&size) {
+            return Ok(None);
+        }
+        let head = hash::head(file)?;
+        self.hash_heads(size, out);
+        let key = (size, head);
+        if !self.heads.contains(&key) {
+            return Ok(None);
+        }
+
+        let hash = hash::file(file)?;
+        let pending = self.by_head.remove(&key).unwrap_or_default();
+        self.hash_files(pending, out);
+        Ok(Some(hash))
+    }
+
+    /// Hash heads of files of `size` not hashed yet.
+    fn hash_heads(&mut self, size: u64, out: &Output) {
+        for (i, f) in self.by_size.remove(&size).unwrap_or_default() {
+            if let Some(head) = out.omit_and_log_errors(hash::head(&f)) {
+                self.heads.insert((size, head.clone()));
+                self.by_head.entry((size, head)).or_default().push((i, f));
+            }
+        }
+    }
+
+    fn hash_files(&mut self, pending: Vec<Pending>, out: &Output) {
+        for (i, f) in pending {
+            if let Some(hash) = out.omit_and_log_errors(hash::file(&f)) {
+                self.trees[i].insert(f, Some(hash));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lazy::LazyTrees;
+    use crate::tests::{dirs, hash_all, matches, quiet};
+
+    #[test]
+    fn hash_like_all() {
+        let dir = tempfile::tempdir().unwrap();
+        let (sub_dir, super_dir) = dirs(dir.path());
+        let out = quiet();
+
+        let files = vec![crate::collect_files(&super_dir, &out)];
+        let mut lazy = LazyTrees::new(&[super_dir.as_path()], files);
+        let mut sub = vec![];
+        for (f, size) in crate::walk_sized(&sub_dir, &out) {
+            let hash = lazy.hash(&f, size, &out).unwrap();
+            sub.push((f, hash));
+        }
+        let supers = hash_all(&super_dir)
+            .into_iter()
+            .filter(|(f, h)| {
+                lazy.trees()[0]
+                    .files_with_hash(h.as_ref().unwrap())
+                    .contains(f)
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            matches(&sub, &supers),
+            matches(&hash_all(&sub_dir), &hash_all(&super_dir))
+        );
+        assert_eq!(sub.iter().filter(|(_, h)| h.is_none()).count(), 2);
+        assert_eq!(out.errors.get(), 0);
+    }
+}
@@ -1,48 +1,1004 @@
 mod cli;
+mod compare;
+mod copy;
+mod delete;
 mod filesystem;
 mod hash;
+mod lazy;
+mod manifest;
+mod verify;
 
-use hashbrown::HashSet;
-use std::io;
+use cli::{Args, Command};
+use compare::{Comparison, Match, Tree};
+use copy::Copied;
+use delete::{Deleter, Present};
+use hashbrown::{HashMap, HashSet};
+use lazy::LazyTrees;
+use manifest::Format;
+use std::cell::Cell;
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::slice;
+use verify::Status;
+
+/// A file along with its hash, if it may share content with a file it's
+/// compared to.
+type Hashed = (PathBuf, Option<String>);
+
+/// Exit statuses.
+const SUBSET: i32 = 0;
+const NOT_SUBSET: i32 = 1;
+pub const USAGE_ERROR: i32 = 2;
+const IO_ERROR: i32 = 3;
+
+/// SUBDIR files of a hash that SUPERDIRs have fewer copies of.
+struct Shortfall {
+    hash: String,
+    files: Vec<PathBuf>,
+    super_copies: usize,
+}
+
+/// Standard output of a run, silenced if quiet, counting the I/O errors
+/// logged to it.
+struct Output {
+    quiet: bool,
+    errors: Cell<usize>,
+}
+
+/// A file on its way to being hashed, if it may share content with a file of
+/// the other side.
+enum Candidate {
+    /// Shares content with no file of the other side.
+    Unique,
+    Sized(u64),
+    /// Size and hash of the head.
+    Head(u64, String),
+    Hashed(String),
+}
+
+/// Files of SUBDIR or a SUPERDIR, walked from a directory along with their
+/// sizes or listed by a manifest along with their hashes.
+type Candidates = Vec<(PathBuf, Candidate)>;
 
 fn main() {
     let args = cli::get_args();
+    let out = Output {
+        quiet: args.quiet,
+        errors: Cell::new(0),
+    };
+
+    let subset = match &args.command {
+        Command::Compare {
+            sub_dir,
+            super_dirs,
+        } => compare_dirs(sub_dir, super_dirs, &args, &out),
+        Command::Manifest {
+            dir,
+            output,
+            format,
+        } => {
+            write_manifest(dir, output, *format, &out);
+            true
+        }
+        Command::Verify {
+            manifest,
+            dir,
+            update,
+        } => verify_manifest(manifest, dir.as_deref(), *update, &out),
+    };
+
+    // A file that couldn't be read may be missing, so errors come first.
+    if out.errors.get() > 0 {
+        process::exit(IO_ERROR);
+    } else if !subset {
+        process::exit(NOT_SUBSET);
+    } else {
+        process::exit(SUBSET);
+    }
+}
+
+/// Print SUBDIR files short of copies in SUPERDIRs, or how every file
+/// compares if reporting, returning whether all SUBDIR files have enough.
+fn compare_dirs(sub_dir: &Path, super_dirs: &[PathBuf], args: &Args, out: &Output) -> bool {
+    // Only reports and multisets need all of SUBDIR at once.
+    if !args.report && !args.multiset && !sub_dir.is_file() {
+        return compare_streamed(sub_dir, super_dirs, args, out);
+    }
+
+    out.print("Collecting SUBDIR files...");
+    let sub_dir_files = collect_files(sub_dir, out);
+    out.print("Collecting SUPERDIR files...");
+    let super_dir_files = super_dirs
+        .iter()
+        .map(|d| collect_files(d, out))
+        .collect::<Vec<Candidates>>();
+
+    let (sub_dir_file_hashes, super_dir_file_hashes) = if matches!(args.match_mode, Match::Path)
+        && !args.report
+        && !args.delete_present
+        && !args.multiset
+    {
+        let supers = super_dir_files.into_iter().map(unhashed).collect();
+        (unhashed(sub_dir_files), supers)
+    } else {
+        out.print("Hashing candidate files...");
+        hash_candidates(sub_dir_files, super_dir_files, out)
+    };
+    let super_trees = super_dirs
+        .iter()
+        .zip(super_dir_file_hashes)
+        .map(|(d, hashes)| Tree::new(root(d), hashes))
+        .collect::<Vec<Tree>>();
+
+    out.print("Checking files...");
+    let mut checker = Checker::new(sub_dir, super_dirs, args, out);
+    for (sub_file, sub_hash) in &sub_dir_file_hashes {
+        checker.check(sub_file, sub_hash.as_ref(), &super_trees);
+    }
+
+    let shortfalls = if args.multiset {
+        // Like copies to delete by, copies inside SUBDIR don't count.
+        let canonical_sub_dir = if sub_dir.is_file() {
+            None
+        } else {
+            out.omit_and_log_errors(fs::canonicalize(sub_dir))
+        };
+        let supers = super_dirs.iter().zip(&super_trees).collect::<Vec<_>>();
+        shortfalls(&sub_dir_file_hashes, &supers, canonical_sub_dir.as_deref())
+    } else {
+        vec![]
+    };
+
+    if args.report {
+        let sub_tree = Tree::new(root(sub_dir), sub_dir_file_hashes);
+        report(&sub_tree, &super_trees, out);
+    }
+    print_shortfalls(&shortfalls, args.report, out);
+
+    checker.finish() && shortfalls.is_empty()
+}
+
+/// Check SUBDIR files as they're walked, hashing them and SUPERDIR files
+/// that may share their content only as they come up.
+fn compare_streamed(sub_dir: &Path, super_dirs: &[PathBuf], args: &Args, out: &Output) -> bool {
+    out.print("Collecting SUPERDIR files...");
+    let super_dir_files = super_dirs
+        .iter()
+        .map(|d| collect_files(d, out))
+        .collect::<Vec<Candidates>>();
+    let roots = super_dirs.iter().map(|d| root(d)).collect::<Vec<&Path>>();
+    let mut supers = LazyTrees::new(&roots, super_dir_files);
+    let hashed = !matches!(args.match_mode, Match::Path) || args.delete_present;
+
+    out.print("Checking files...");
+    let mut checker = Checker::new(sub_dir, super_dirs, args, out);
+    for (sub_file, size) in walk_sized(sub_dir, out) {
+        let sub_hash = if hashed {
+            match out.omit_and_log_errors(supers.hash(&sub_file, size, out)) {
+                Some(hash) => hash,
+                None => continue,
+            }
+        } else {
+            None
+        };
+        checker.check(&sub_file, sub_hash.as_ref(), supers.trees());
+    }
+
+    checker.finish()
+}
+
+/// Checker of SUBDIR files one at a time against SUPERDIR trees, printing
+/// those short of copies and gathering those to copy or delete.
+struct Checker<'a> {
+    sub_dir: &'a Path,
+    super_dirs: &'a [PathBuf],
+    args: &'a Args,
+    out: &'a Output,
+    /// Copies are only trusted outside SUBDIR, which may be inside a
+    /// SUPERDIR.
+    canonical_sub_dir: Option<PathBuf>,
+    subset: bool,
+    missing: Vec<Hashed>,
+    present: Vec<Present>,
+}
+
+impl<'a> Checker<'a> {
+    fn new(sub_dir: &'a Path, super_dirs: &'a [PathBuf], args: &'a Args, out: &'a Output) -> Self {
+        let canonical_sub_dir = if args.delete_present && !sub_dir.is_file() {
+            out.omit_and_log_errors(fs::canonicalize(sub_dir))
+        } else {
+            None
+        };
+
+        Checker {
+            sub_dir,
+            super_dirs,
+            args,
+            out,
+            canonical_sub_dir,
+            subset: true,
+            missing: vec![],
+            present: vec![],
+        }
+    }
+
+    fn check(&mut self, sub_file: &Path, sub_hash: Option<&String>, super_trees: &[Tree]) {
+        let (args, out) = (self.args, self.out);
+        let relative = compare::relative(root(self.sub_dir), sub_file);
+        let holders = self
+            .super_dirs
+            .iter()
+            .zip(super_trees)
+            .filter(|(_, t)| t.contains(relative, sub_hash, args.match_mode))
+            .collect::<Vec<(&PathBuf, &Tree)>>();
+        if holders.len() >= args.min_copies {
+            if let (Some(sub_dir), Some(hash)) = (&self.canonical_sub_dir, sub_hash) {
+                self.present.extend(delete::find_present(
+                    sub_file,
+                    hash,
+                    &holders,
+                    sub_dir,
+                    args.min_copies,
+                ));
+            }
+            return;
+        }
+        let holders = holders.into_iter().map(|(d, _)| d).collect::<Vec<_>>();
+
+        self.subset = false;
+        if args
+            .copy_missing
+            .as_ref()
+            .is_some_and(|dest| !holders.contains(&dest))
+        {
+            self.missing
+                .push((sub_file.to_path_buf(), sub_hash.cloned()));
+        }
+
+        // Reports classify every file on their own.
+        if args.report {
+            return;
+        }
+
+        if holders.is_empty() {
+            let supers = self
+                .super_dirs
+                .iter()
+                .map(|d| d.display().to_string())
+                .collect::<Vec<String>>();
+            out.print(format_args!(
+                "{} not in {}",
+                sub_file.display(),
+                supers.join(", ")
+            ));
+        } else {
+            let holders = holders
+                .iter()
+                .map(|d| d.display().to_string())
+                .collect::<Vec<String>>();
+            out.print(format_args!(
+                "{} only in {}",
+                sub_file.display(),
+                holders.join(", ")
+            ));
+        }
+    }
+
+    /// Copy missing and delete present files as asked, returning whether
+    /// all SUBDIR files checked had enough copies.
+    fn finish(self) -> bool {
+        let (args, out) = (self.args, self.out);
+        if let Some(dest) = &args.copy_missing {
+            copy_missing(self.sub_dir, &self.missing, dest, args.dry_run, out);
+        }
+
+        if let Some(journal) = args.journal.as_ref().filter(|_| args.delete_present) {
+            delete_present(self.sub_dir, &self.present, journal, args, out);
+        }
+
+        self.subset
+    }
+}
+
+/// Delete `present` SUBDIR files, recording them to `journal`.
+fn delete_present(sub_dir: &Path, present: &[Present], journal: &Path, args: &Args, out: &Output) {
+    if sub_dir.is_file() {
+        out.omit_and_log_errors::<()>(Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "can't delete files listed by manifest {}",
+                sub_dir.display()
+            ),
+        )));
+        return;
+    }
+
+    let mut deleter =
+        match out.omit_and_log_errors(Deleter::new(journal, args.verify_bytes, args.dry_run)) {
+            Some(deleter) => deleter,
+            None => return,
+        };
+
+    out.print("Deleting present files...");
+    let deleted = if args.dry_run {
+        "Would delete"
+    } else {
+        "Deleted"
+    };
+    for p in present {
+        match out.omit_and_log_errors(deleter.delete(p)) {
+            Some(true) => out.print(format_args!("{} {}", deleted, p.file.display())),
+            Some(false) => out.print(format_args!(
+                "Kept {}, no SUPERDIR copy has identical bytes",
+                p.file.display()
+            )),
+            None => {}
+        }
+    }
+}
+
+/// Hashes of SUBDIR files that SUPERDIRs have some, but fewer copies of,
+/// each copy only making up for one SUBDIR file. Copies of walked SUPERDIRs
+/// only count outside of `sub_dir`, a canonical path, if given. Hashes
+/// without any copies, even inside SUBDIR, are left to be reported per
+/// file.
+fn shortfalls(
+    sub_dir_file_hashes: &[Hashed],
+    supers: &[(&PathBuf, &Tree)],
+    sub_dir: Option<&Path>,
+) -> Vec<Shortfall> {
+    let mut counts = HashMap::<&String, Vec<&PathBuf>>::new();
+    for (file, hash) in sub_dir_file_hashes {
+        if let Some(hash) = hash {
+            counts.entry(hash).or_default().push(file);
+        }
+    }
 
-    let sub_dir = args.sub_dir;
-    let super_dir = args.super_dir;
+    let mut shortfalls = counts
+        .into_iter()
+        .filter_map(|(hash, mut files)| {
+            let super_copies = supers
+                .iter()
+                .map(|(super_dir, tree)| {
+                    let copies = tree.files_with_hash(hash);
+                    match sub_dir.filter(|_| !super_dir.is_file()) {
+                        Some(sub_dir) => copies
+                            .iter()
+                            .filter(|c| delete::is_outside(c, sub_dir))
+                            .count(),
+                        None => copies.len(),
+                    }
+                })
+                .sum::<usize>();
+            // Hashes in no SUPERDIR at all are reported per file instead.
+            let in_supers = supers.iter().any(|(_, t)| t.contains_hash(Some(hash)));
+            if !in_supers || super_copies >= files.len() {
+                return None;
+            }
+
+            files.sort();
+            Some(Shortfall {
+                hash: hash.clone(),
+                files: files.into_iter().cloned().collect(),
+                super_copies,
+            })
+        })
+        .collect::<Vec<Shortfall>>();
+    shortfalls.sort_by(|a, b| a.files[0].cmp(&b.files[0]));
 
-    println!("Collecting SUBDIR files...");
-    let sub_dir_files = filesystem::walk_files(&sub_dir).filter_map(omit_and_log_errors);
-    println!("Collecting SUPERDIR files...");
-    let super_dir_files = filesystem::walk_files(&super_dir).filter_map(omit_and_log_errors);
+    shortfalls
+}
+
+/// Print shortfalls with their SUBDIR files, or as report lines of hash and
+/// counts.
+fn print_shortfalls(shortfalls: &[Shortfall], report: bool, out: &Output) {
+    for s in shortfalls {
+        if report {
+            out.print(format_args!(
+                "shortfall\t{}\t{}\t{}",
+                s.hash,
+                s.files.len(),
+                s.super_copies
+            ));
+            continue;
+        }
+
+        out.print(format_args!(
+            "{} copies in SUBDIR, {} in SUPERDIR of {}",
+            s.files.len(),
+            s.super_copies,
+            s.hash
+        ));
+        for f in s.files.iter() {
+            out.print(format_args!("\t{}", f.display()));
+        }
+    }
+}
+
+/// Copy `missing` SUBDIR files to their relative paths under `dest`.
+fn copy_missing(sub_dir: &Path, missing: &[Hashed], dest: &Path, dry_run: bool, out: &Output) {
+    if sub_dir.is_file() {
+        out.omit_and_log_errors::<()>(Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("can't copy files listed by manifest {}", sub_dir.display()),
+        )));
+        return;
+    }
+
+    out.print("Copying missing files...");
+    let copied = if dry_run { "Would copy" } else { "Copied" };
+    for (file, hash) in missing {
+        let target = dest.join(compare::relative(sub_dir, file));
+        match out.omit_and_log_errors(copy::file(file, hash.as_deref(), target, dry_run)) {
+            Some(Copied::To(copy)) => out.print(format_args!(
+                "{} {} to {}",
+                copied,
+                file.display(),
+                copy.display()
+            )),
+            Some(Copied::Present(copy)) => out.print(format_args!(
+                "{} already at {}",
+                file.display(),
+                copy.display()
+            )),
+            None => {}
+        }
+    }
+}
+
+/// Write a manifest of all files of `dir`, except `output` itself, to
+/// `output`.
+fn write_manifest(dir: &Path, output: &Path, format: Format, out: &Output) {
+    let is_output = same_file(output);
+
+    out.print("Collecting files...");
+    let files = filesystem::walk_files(dir, out)
+        .filter_map(|r| out.omit_and_log_errors(r))
+        .filter(|f| !is_output(f))
+        .collect::<Vec<PathBuf>>();
+
+    out.print("Hashing files...");
+    let mut entries = files
+        .into_iter()
+        .map(|f| {
+            let relative = compare::relative(dir, &f).to_path_buf();
+            // Manifests are text, so can't list such paths.
+            if relative.to_str().is_none() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} is not valid UTF-8, so left unlisted", f.display()),
+                ));
+            }
+            Ok((relative, hash::file(&f)?))
+        })
+        .filter_map(|r| out.omit_and_log_errors(r))
+        .collect::<Vec<(PathBuf, String)>>();
+    entries.sort();
+
+    out.omit_and_log_errors(save_manifest(output, &entries, format));
+}
+
+/// Print the status of each file listed by `manifest_path` under `dir`, or
+/// next to the manifest, then of files there it doesn't list, returning
+/// whether all listed files are ok. Hashes of changed files are updated if
+/// `update`.
+fn verify_manifest(manifest_path: &Path, dir: Option<&Path>, update: bool, out: &Output) -> bool {
+    let dir = match (dir, manifest_path.parent()) {
+        (Some(dir), _) => dir,
+        (None, Some(parent)) if parent != Path::new("") => parent,
+        (None, _) => Path::new("."),
+    };
+
+    let read = fs::metadata(manifest_path)
+        .and_then(|m| m.modified())
+        .and_then(|written| Ok((manifest::read(manifest_path)?, written)));
+    let (mut manifest, written) = match out.omit_and_log_errors(read) {
+        Some(read) => read,
+        None => return false,
+    };
+
+    out.print("Verifying files...");
+    let mut intact = true;
+    let mut updated = false;
+    for (relative, hash) in manifest.entries.iter_mut() {
+        let status = match out.omit_and_log_errors(verify::file(dir.join(&relative), hash, written))
+        {
+            Some(status) => status,
+            None => {
+                intact = false;
+                continue;
+            }
+        };
+        out.print(format_args!("{}\t{}", status.name(), relative.display()));
+
+        match status {
+            Status::Ok => {}
+            Status::Changed(actual) if update => {
+                *hash = actual;
+                updated = true;
+            }
+            _ => intact = false,
+        }
+    }
 
-    let sub_dir_file_hashes = sub_dir_files
-        .map(|f| match hash::file(&f) {
-            Ok(h) => Ok((f, h)),
+    let listed = manifest
+        .entries
+        .iter()
+        .map(|(f, _)| f.as_path())
+        .collect::<HashSet<&Path>>();
+    let is_manifest = same_file(manifest_path);
+    let mut new = filesystem::walk_files(dir, out)
+        .filter_map(|r| out.omit_and_log_errors(r))
+        .filter(|f| !is_manifest(f))
+        .map(|f| compare::relative(dir, &f).to_path_buf())
+        .filter(|f| !listed.contains(f.as_path()))
+        .collect::<Vec<PathBuf>>();
+    new.sort();
+    for f in new {
+        out.print(format_args!("{}\t{}", Status::New.name(), f.display()));
+    }
+
+    if updated {
+        out.omit_and_log_errors(save_manifest(
+            manifest_path,
+            &manifest.entries,
+            manifest.format,
+        ));
+    }
+
+    intact
+}
+
+/// Write a manifest to a temporary file next to `path`, then move it over
+/// `path`, so a failed write leaves any previous manifest intact.
+fn save_manifest(path: &Path, entries: &[(PathBuf, String)], format: Format) -> io::Result<()> {
+    let mut temporary = path.as_os_str().to_os_string();
+    temporary.push(".tmp");
+
+    let saved = write_synced(Path::new(&temporary), entries, format)
+        .and_then(|_| fs::rename(&temporary, path));
+    if saved.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    saved
+}
+
+fn write_synced(path: &Path, entries: &[(PathBuf, String)], format: Format) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    manifest::write(&mut writer, entries, format)?;
+    writer.into_inner()?.sync_all()
+}
+
+/// Predicate of whether a path leads to the same file as `path`, if it
+/// exists.
+fn same_file(path: &Path) -> impl Fn(&Path) -> bool + '_ {
+    let canonical = fs::canonicalize(path).ok();
+    move |f: &Path| {
+        canonical.is_some()
+            && f.file_name() == path.file_name()
+            && fs::canonicalize(f).ok() == canonical
+    }
+}
+
+/// Print how every file of SUBDIR and SUPERDIRs compares.
+fn report(sub_tree: &Tree, super_trees: &[Tree], out: &Output) {
+    for c in compare::compare(sub_tree, super_trees) {
+        match c {
+            Comparison::Both { sub, sup } => {
+                out.print(format_args!("both\t{}\t{}", sub.display(), sup.display()))
+            }
+            Comparison::Moved { sub, supers } => {
+                for sup in supers {
+                    out.print(format_args!("moved\t{}\t{}", sub.display(), sup.display()));
+                }
+            }
+            Comparison::SubOnly(sub) => out.print(format_args!("sub-only\t{}", sub.display())),
+            Comparison::SuperOnly(sup) => out.print(format_args!("super-only\t{}", sup.display())),
+            Comparison::Modified { sub, sup } => out.print(format_args!(
+                "modified\t{}\t{}",
+                sub.display(),
+                sup.display()
+            )),
+        }
+    }
+}
+
+/// Stream all files of directory with their sizes, logging and omitting
+/// failures.
+fn walk_sized<'a>(directory: &Path, out: &'a Output) -> impl Iterator<Item = (PathBuf, u64)> + 'a {
+    filesystem::walk_files(directory, out)
+        .filter_map(move |r| out.omit_and_log_errors(r))
+        .map(|f| match fs::metadata(&f) {
+            Ok(m) => Ok((f, m.len())),
             Err(err) => Err(err),
         })
-        .filter_map(omit_and_log_errors); // Intentionally lazy.
-    println!("Hashing SUPERDIR files...");
-    let super_dir_hashes = super_dir_files
+        .filter_map(move |r| out.omit_and_log_errors(r))
+}
+
+/// Collect files of `path`, a manifest if it's a file and otherwise a
+/// directory.
+fn collect_files(path: &Path, out: &Output) -> Candidates {
+    if !path.is_file() {
+        return walk_sized(path, out)
+            .map(|(f, size)| (f, Candidate::Sized(size)))
+            .collect();
+    }
+
+    let entries = out
+        .omit_and_log_errors(manifest::read(path).map(|m| m.entries))
+        .unwrap_or_default();
+    entries
         .into_iter()
-        .map(|f| hash::file(f))
-        .filter_map(omit_and_log_errors)
-        .collect::<HashSet<String>>();
+        .map(|(f, h)| (f, Candidate::Hashed(h)))
+        .collect()
+}
 
-    println!("Checking files...");
-    for (sub_file, sub_hash) in sub_dir_file_hashes {
-        if !super_dir_hashes.contains(&sub_hash) {
-            println!("{} not in {}", sub_file.display(), super_dir.display());
+/// Root that paths of files collected from `path` are relative to, which
+/// manifests list relative paths of already.
+fn root(path: &Path) -> &Path {
+    if path.is_file() {
+        Path::new("")
+    } else {
+        path
+    }
+}
+
+fn unhashed(files: Candidates) -> Vec<Hashed> {
+    files
+        .into_iter()
+        .map(|(f, c)| match c {
+            Candidate::Hashed(h) => (f, Some(h)),
+            _ => (f, None),
+        })
+        .collect()
+}
+
+/// Hash only the files of SUBDIR and SUPERDIRs that may share content with a
+/// file of the other side, as told by sizes and then by hashes of their
+/// heads, where known for all files of the other side. The others are left
+/// without a hash.
+fn hash_candidates(
+    sub_files: Candidates,
+    super_files: Vec<Candidates>,
+    out: &Output,
+) -> (Vec<Hashed>, Vec<Vec<Hashed>>) {
+    let sub_sizes = sizes(slice::from_ref(&sub_files));
+    let super_sizes = sizes(&super_files);
+    // Heads can only be matched if both sides have them.
+    let heads = sub_sizes.is_some() && super_sizes.is_some();
+
+    let sub_files = hash_heads(sub_files, super_sizes.as_ref(), heads, out);
+    let super_files = super_files
+        .into_iter()
+        .map(|files| hash_heads(files, sub_sizes.as_ref(), heads, out))
+        .collect::<Vec<Candidates>>();
+
+    let sub_keys = head_keys(slice::from_ref(&sub_files));
+    let super_keys = head_keys(&super_files);
+    let super_hashes = super_files
+        .into_iter()
+        .map(|files| hash_matching(files, sub_keys.as_ref(), out))
+        .collect();
+
+    (
+        hash_matching(sub_files, super_keys.as_ref(), out),
+        super_hashes,
+    )
+}
+
+/// Sizes of all files of `sides`, unless some are listed without one.
+fn sizes(sides: &[Candidates]) -> Option<HashSet<u64>> {
+    let mut sizes = HashSet::new();
+    for (_, candidate) in sides.iter().flatten() {
+        match candidate {
+            Candidate::Sized(size) => {
+                sizes.insert(*size);
+            }
+            Candidate::Hashed(_) => return None,
+            Candidate::Unique | Candidate::Head(..) => {}
         }
     }
+
+    Some(sizes)
 }
 
-fn omit_and_log_errors<T>(result: io::Result<T>) -> Option<T> {
-    match result {
-        Ok(value) => Some(value),
-        Err(err) => {
-            println!("{}", err);
-            None
+/// Rule out files sized like none of `sizes`, if known, and hash heads of
+/// the rest if `heads`.
+fn hash_heads(
+    files: Candidates,
+    sizes: Option<&HashSet<u64>>,
+    heads: bool,
+    out: &Output,
+) -> Candidates {
+    files
+        .into_iter()
+        .map(|(f, candidate)| match candidate {
+            Candidate::Sized(size) if sizes.is_some_and(|s| !s.contains(&size)) => {
+                Ok((f, Candidate::Unique))
+            }
+            Candidate::Sized(size) if heads => match hash::head(&f) {
+                Ok(head) => Ok((f, Candidate::Head(size, head))),
+                Err(err) => Err(err),
+            },
+            candidate => Ok((f, candidate)),
+        })
+        .filter_map(|r| out.omit_and_log_errors(r))
+        .collect()
+}
+
+/// Sizes and heads of all files of `sides`, unless some are without one.
+fn head_keys(sides: &[Candidates]) -> Option<HashSet<(u64, String)>> {
+    let mut keys = HashSet::new();
+    for (_, candidate) in sides.iter().flatten() {
+        match candidate {
+            Candidate::Head(size, head) => {
+                keys.insert((*size, head.clone()));
+            }
+            Candidate::Sized(_) | Candidate::Hashed(_) => return None,
+            Candidate::Unique => {}
         }
     }
+
+    Some(keys)
+}
+
+/// Hash files not ruled out, by their size and head matching none of `keys`
+/// if known.
+fn hash_matching(
+    files: Candidates,
+    keys: Option<&HashSet<(u64, String)>>,
+    out: &Output,
+) -> Vec<Hashed> {
+    files
+        .into_iter()
+        .map(|(f, candidate)| match candidate {
+            Candidate::Head(size, head)
+                if keys.is_some_and(|k| !k.contains(&(size, head.clone()))) =>
+            {
+                Ok((f, None))
+            }
+            Candidate::Sized(_) | Candidate::Head(..) => match hash::file(&f) {
+                Ok(h) => Ok((f, Some(h))),
+                Err(err) => Err(err),
+            },
+            Candidate::Hashed(h) => Ok((f, Some(h))),
+            Candidate::Unique => Ok((f, None)),
+        })
+        .filter_map(|r| out.omit_and_log_errors(r))
+        .collect()
+}
+
+impl Output {
+    fn print<D>(&self, line: D)
+    where
+        D: Display,
+    {
+        if !self.quiet {
+            println!("{}", line);
+        }
+    }
+
+    fn omit_and_log_errors<T>(&self, result: io::Result<T>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(err) => {
+                self.errors.set(self.errors.get() + 1);
+                self.print(err);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compare::Tree;
+    use crate::manifest::{self, Format};
+    use crate::{hash, Candidate, Hashed, Output, Shortfall};
+    use std::cell::Cell;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    pub fn quiet() -> Output {
+        Output {
+            quiet: true,
+            errors: Cell::new(0),
+        }
+    }
+
+    /// Files of SUBDIR and SUPERDIR, some telling apart only by size, head or
+    /// the rest of their content.
+    pub fn dirs(dir: &Path) -> (PathBuf, PathBuf) {
+        let sub_dir = dir.join("sub");
+        let super_dir = dir.join("super");
+        let long = vec![b'x'; 5000];
+        let mut tail = long.clone();
+        tail[4999] = b'y';
+        let mut head = long.clone();
+        head[0] = b'y';
+        let mut other_head = long.clone();
+        other_head[0] = b'z';
+
+        for (d, files) in [
+            (
+                &sub_dir,
+                vec![("a", &long), ("b", &tail), ("c", &head), ("f", &other_head)],
+            ),
+            (&super_dir, vec![("a", &long), ("b", &head), ("c", &long)]),
+        ] {
+            fs::create_dir(d).unwrap();
+            for (f, content) in files {
+                fs::write(d.join(f), content).unwrap();
+            }
+        }
+        fs::write(sub_dir.join("d"), b"Hello").unwrap();
+        fs::write(super_dir.join("d"), b"Hello").unwrap();
+        fs::write(sub_dir.join("e"), b"Short").unwrap();
+        fs::write(super_dir.join("e"), b"Longer").unwrap();
+
+        (sub_dir, super_dir)
+    }
+
+    /// Pairs of SUBDIR and SUPERDIR files with the same hash.
+    pub fn matches(sub: &[Hashed], supers: &[Hashed]) -> Vec<(PathBuf, PathBuf)> {
+        let mut matches = vec![];
+        for (sub_file, sub_hash) in sub {
+            for (super_file, super_hash) in supers {
+                if sub_hash.is_some() && sub_hash == super_hash {
+                    matches.push((sub_file.clone(), super_file.clone()));
+                }
+            }
+        }
+        matches.sort();
+        matches
+    }
+
+    pub fn hash_all(dir: &Path) -> Vec<Hashed> {
+        crate::walk_sized(dir, &quiet())
+            .map(|(f, _)| {
+                let hash = hash::file(&f).unwrap();
+                (f, Some(hash))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn hash_candidates_like_all() {
+        let dir = tempfile::tempdir().unwrap();
+        let (sub_dir, super_dir) = dirs(dir.path());
+        let out = quiet();
+
+        let (sub, supers) = crate::hash_candidates(
+            crate::collect_files(&sub_dir, &out),
+            vec![crate::collect_files(&super_dir, &out)],
+            &out,
+        );
+        assert_eq!(
+            matches(&sub, &supers[0]),
+            matches(&hash_all(&sub_dir), &hash_all(&super_dir))
+        );
+        let unhashed = sub.iter().filter(|(_, h)| h.is_none()).count();
+        assert_eq!(unhashed, 2);
+
+        // Files listed by a manifest can't be ruled out.
+        let listed = hash_all(&super_dir)
+            .into_iter()
+            .map(|(f, h)| (f, Candidate::Hashed(h.unwrap())))
+            .collect();
+        let (sub, supers) =
+            crate::hash_candidates(crate::collect_files(&sub_dir, &out), vec![listed], &out);
+        assert!(sub.iter().all(|(_, h)| h.is_some()));
+        assert_eq!(
+            matches(&sub, &supers[0]),
+            matches(&hash_all(&sub_dir), &hash_all(&super_dir))
+        );
+        assert_eq!(out.errors.get(), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_manifest_skips_non_utf8() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a"), b"Hello").unwrap();
+        fs::write(dir.path().join(OsStr::from_bytes(b"b\xff")), b"Hello").unwrap();
+        let output = dir.path().join("sums");
+        let out = quiet();
+
+        crate::write_manifest(dir.path(), &output, Format::Gnu, &out);
+        assert_eq!(out.errors.get(), 1);
+        let entries = manifest::read(&output).unwrap().entries;
+        assert_eq!(
+            entries,
+            [(
+                PathBuf::from("a"),
+                hash::file(dir.path().join("a")).unwrap()
+            )]
+        );
+        assert!(!dir.path().join("sums.tmp").exists());
+    }
+
+    #[test]
+    fn save_manifest_removes_temporary() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("sums");
+        fs::create_dir(&output).unwrap();
+        fs::write(output.join("a"), b"Hello").unwrap();
+
+        let entries = [(PathBuf::from("a"), "0".repeat(64))];
+        assert!(crate::save_manifest(&output, &entries, Format::Gnu).is_err());
+        assert!(!dir.path().join("sums.tmp").exists());
+    }
+
+    fn hashed(files: &[(&Path, &str)]) -> Vec<Hashed> {
+        files
+            .iter()
+            .map(|(f, h)| (f.to_path_buf(), Some(h.to_string())))
+            .collect()
+    }
+
+    fn describe(shortfalls: &[Shortfall]) -> Vec<(String, usize, usize)> {
+        shortfalls
+            .iter()
+            .map(|s| (s.hash.clone(), s.files.len(), s.super_copies))
+            .collect()
+    }
+
+    #[test]
+    fn shortfalls_across_supers() {
+        let mut sub = hashed(&[
+            (Path::new("sub/a"), "1"),
+            (Path::new("sub/b"), "1"),
+            (Path::new("sub/c"), "2"),
+            (Path::new("sub/d"), "2"),
+            (Path::new("sub/e"), "2"),
+            (Path::new("sub/f"), "3"),
+        ]);
+        sub.push((PathBuf::from("sub/g"), None));
+        let one = Tree::new(
+            "one",
+            hashed(&[(Path::new("one/a"), "1"), (Path::new("one/c"), "2")]),
+        );
+        let two = Tree::new(
+            "two",
+            hashed(&[(Path::new("two/a"), "1"), (Path::new("two/c"), "2")]),
+        );
+        let (one_dir, two_dir) = (PathBuf::from("one"), PathBuf::from("two"));
+
+        // Hashes in no SUPERDIR are left to be reported per file.
+        let supers = [(&one_dir, &one), (&two_dir, &two)];
+        assert_eq!(
+            describe(&crate::shortfalls(&sub, &supers, None)),
+            [("2".to_string(), 3, 2)]
+        );
+        assert_eq!(
+            describe(&crate::shortfalls(&sub, &supers[..1], None)),
+            [("1".to_string(), 2, 1), ("2".to_string(), 3, 1)]
+        );
+    }
+
+    #[test]
+    fn shortfalls_not_counting_sub_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = fs::canonicalize(dir.path()).unwrap();
+        let sub_dir = dir.join("sub");
+        fs::create_dir(&sub_dir).unwrap();
+        let (a, b, c) = (sub_dir.join("a"), sub_dir.join("b"), dir.join("c"));
+        for f in [&a, &b, &c] {
+            fs::write(f, b"Hello").unwrap();
+        }
+
+        let sub = hashed(&[(&a, "1"), (&b, "1")]);
+        let sup = Tree::new(&dir, hashed(&[(&a, "1"), (&b, "1"), (&c, "1")]));
+        let supers = [(&dir, &sup)];
+        assert!(crate::shortfalls(&sub, &supers, None).is_empty());
+        let shortfalls = crate::shortfalls(&sub, &supers, Some(&sub_dir));
+        assert_eq!(describe(&shortfalls), [("1".to_string(), 2, 1)]);
+        assert_eq!(shortfalls[0].files, [a, b]);
+    }
 }
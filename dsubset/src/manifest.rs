@@ -0,0 +1,223 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+const BSD_PREFIX: &str = "SHA256 (";
+const BSD_SEPARATOR: &str = ") = ";
+
+/// Line format of a manifest.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// `HASH  PATH`, as written by `sha256sum`.
+    Gnu,
+    /// `SHA256 (PATH) = HASH`, as written by `sha256sum --tag`.
+    Bsd,
+}
+
+/// Write paths and their hashes to `out` in `format`.
+///
+/// Like `sha256sum`, backslashes and newlines in paths are escaped and their
+/// lines prefixed by a backslash.
+pub fn write<W>(out: &mut W, entries: &[(PathBuf, String)], format: Format) -> io::Result<()>
+where
+    W: Write,
+{
+    for (path, hash) in entries {
+        let path = path.to_str().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not valid UTF-8", path.display()),
+            )
+        })?;
+        let escaped = path.replace('\\', "\\\\").replace('\n', "\\n");
+        let prefix = if escaped != path { "\\" } else { "" };
+
+        match format {
+            Format::Gnu => writeln!(out, "{}{}  {}", prefix, hash, escaped)?,
+            Format::Bsd => writeln!(
+                out,
+                "{}{}{}{}{}",
+                prefix, BSD_PREFIX, escaped, BSD_SEPARATOR, hash
+            )?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Paths and their hashes read from a manifest.
+#[derive(Debug)]
+pub struct Manifest {
+    /// Format of the first line, or [`Format::Gnu`] if empty.
+    pub format: Format,
+    pub entries: Vec<(PathBuf, String)>,
+}
+
+/// Read a manifest in either format.
+pub fn read<P>(path: P) -> io::Result<Manifest>
+where
+    P: AsRef<Path>,
+{
+    let reader = BufReader::new(File::open(&path)?);
+
+    let mut format = None;
+    let mut entries = vec![];
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let (entry, line_format) = parse_line(&line).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{}:{}: not a SHA-256 checksum line",
+                    path.as_ref().display(),
+                    number + 1
+                ),
+            )
+        })?;
+        format.get_or_insert(line_format);
+        entries.push(entry);
+    }
+
+    Ok(Manifest {
+        format: format.unwrap_or(Format::Gnu),
+        entries,
+    })
+}
+
+fn parse_line(line: &str) -> Option<((PathBuf, String), Format)> {
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+
+    let (path, hash, format) = if let Some(rest) = line.strip_prefix(BSD_PREFIX) {
+        let separator = rest.rfind(BSD_SEPARATOR)?;
+        (
+            &rest[..separator],
+            &rest[separator + BSD_SEPARATOR.len()..],
+            Format::Bsd,
+        )
+    } else {
+        // The hash is followed by a space and a space or `*` for binary mode.
+        let hash = line.get(..64)?;
+        let path = line.get(64..)?;
+        let path = path
+            .strip_prefix("  ")
+            .or_else(|| path.strip_prefix(" *"))?;
+        (path, hash, Format::Gnu)
+    };
+
+    if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let path = if escaped {
+        unescape(path)?
+    } else {
+        path.to_string()
+    };
+
+    Some(((PathBuf::from(path), hash.to_ascii_lowercase()), format))
+}
+
+fn unescape(path: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(path.len());
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next()? {
+            '\\' => unescaped.push('\\'),
+            'n' => unescaped.push('\n'),
+            _ => return None,
+        }
+    }
+
+    Some(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::manifest::{self, Format};
+    use std::fs;
+    use std::io;
+    use std::path::PathBuf;
+
+    const HASH: &str = "98ea6e4f216f2fb4b69fff9b3a44842c38686ca685f3f55dc48c5d3fb1107be4";
+
+    fn entries() -> Vec<(PathBuf, String)> {
+        ["a", "dir/b c", "back\\slash", "new\nline"]
+            .iter()
+            .map(|p| (PathBuf::from(p), HASH.to_string()))
+            .collect()
+    }
+
+    fn written(format: Format) -> String {
+        let mut out = vec![];
+        manifest::write(&mut out, &entries(), format).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn read(content: &str) -> io::Result<manifest::Manifest> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sums");
+        fs::write(&path, content).unwrap();
+        manifest::read(&path)
+    }
+
+    #[test]
+    fn write_formats() {
+        assert_eq!(
+            written(Format::Gnu),
+            format!(
+                "{h}  a\n{h}  dir/b c\n\\{h}  back\\\\slash\n\\{h}  new\\nline\n",
+                h = HASH
+            )
+        );
+        assert_eq!(
+            written(Format::Bsd),
+            format!(
+                "SHA256 (a) = {h}\nSHA256 (dir/b c) = {h}\n\
+                 \\SHA256 (back\\\\slash) = {h}\n\\SHA256 (new\\nline) = {h}\n",
+                h = HASH
+            )
+        );
+    }
+
+    #[test]
+    fn read_written() {
+        for format in [Format::Gnu, Format::Bsd] {
+            let manifest = read(&written(format)).unwrap();
+            assert_eq!(manifest.format, format);
+            assert_eq!(manifest.entries, entries());
+        }
+    }
+
+    #[test]
+    fn read_binary_marker_and_uppercase() {
+        let manifest = read(&format!("{} *a\n\n", HASH.to_uppercase())).unwrap();
+        assert_eq!(manifest.format, Format::Gnu);
+        assert_eq!(manifest.entries, [(PathBuf::from("a"), HASH.to_string())]);
+    }
+
+    #[test]
+    fn read_bad_lines() {
+        for content in [
+            format!("{} a\n", HASH),
+            format!("{}  a\n", &HASH[1..]),
+            format!("{}  a\n", HASH.replace('9', "g")),
+            format!("\\{}  bad\\escape\n", HASH),
+            format!("SHA256 (a) {}\n", HASH),
+            "not a manifest\n".to_string(),
+        ] {
+            let err = read(&format!("{}  ok\n{}", HASH, content)).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(err.to_string().ends_with(":2: not a SHA-256 checksum line"));
+        }
+    }
+}
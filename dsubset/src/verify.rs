@@ -0,0 +1,84 @@
+use crate::hash;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// State of a file listed by a manifest, or found next to listed files.
+#[derive(Debug)]
+pub enum Status {
+    Ok,
+    /// Different content, modified after the manifest was written, with its
+    /// new hash.
+    Changed(String),
+    /// Different content, though not modified since the manifest was written,
+    /// so most likely rotten.
+    Corrupted,
+    Missing,
+    /// Not listed by the manifest.
+    New,
+}
+
+impl Status {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Changed(_) => "changed",
+            Status::Corrupted => "corrupted",
+            Status::Missing => "missing",
+            Status::New => "new",
+        }
+    }
+}
+
+/// Rehash `file`, listed with `hash` by a manifest `written` at that time.
+pub fn file<P>(file: P, hash: &str, written: SystemTime) -> io::Result<Status>
+where
+    P: AsRef<Path>,
+{
+    let modified = match fs::metadata(&file) {
+        Ok(metadata) => metadata.modified()?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Status::Missing),
+        Err(err) => return Err(err),
+    };
+
+    let actual = hash::file(&file)?;
+    if actual == hash {
+        Ok(Status::Ok)
+    } else if modified > written {
+        Ok(Status::Changed(actual))
+    } else {
+        Ok(Status::Corrupted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hash;
+    use crate::verify::{self, Status};
+    use std::fs;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn statuses() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a");
+        fs::write(&file, b"Hello").unwrap();
+        let hash = hash::file(&file).unwrap();
+        fs::write(&file, b"World").unwrap();
+        let modified = fs::metadata(&file).unwrap().modified().unwrap();
+        let before = modified - Duration::from_secs(1);
+        let after = modified + Duration::from_secs(1);
+
+        let status = |hash: &str, written: SystemTime| verify::file(&file, hash, written).unwrap();
+        let actual = hash::file(&file).unwrap();
+        assert!(matches!(status(&actual, before), Status::Ok));
+        assert!(matches!(status(&actual, after), Status::Ok));
+        assert!(matches!(status(&hash, before), Status::Changed(h) if h == actual));
+        assert!(matches!(status(&hash, after), Status::Corrupted));
+        assert!(matches!(
+            verify::file(dir.path().join("b"), &hash, after).unwrap(),
+            Status::Missing
+        ));
+    }
+}